@@ -0,0 +1,101 @@
+// Local HTTP server standing in for the AWS APIs in tests, the clients are pointed to it
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_s3::config::{Credentials, SharedCredentialsProvider};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+pub(crate) struct StubRequest {
+    pub(crate) method: String,
+    // Path and query, e.g. /bucket?versions
+    pub(crate) uri: String,
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: String,
+}
+
+impl StubRequest {
+    // Operation of the JSON protocols (DynamoDB, KMS), e.g. DynamoDB_20120810.GetItem
+    pub(crate) fn target(&self) -> &str {
+        self.headers.get("x-amz-target").map(|t| t.as_str()).unwrap_or_default()
+    }
+}
+
+type Handler = Arc<dyn Fn(&StubRequest) -> (u16, String) + Send + Sync>;
+
+pub(crate) struct AwsStub {
+    pub(crate) config: SdkConfig,
+    // Method and URI of the requests received, in order
+    pub(crate) requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl AwsStub {
+    pub(crate) async fn start(handler: impl Fn(&StubRequest) -> (u16, String) + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handler: Handler = Arc::new(handler);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (handler, received) = (handler.clone(), received.clone());
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(request) = read_request(&mut reader).await {
+                        received.lock().unwrap().push(format!("{} {}", request.method, request.uri));
+                        let (status, body) = handler(&request);
+                        let response = format!(
+                            "HTTP/1.1 {} Stub\r\ncontent-length: {}\r\ncontent-type: application/xml\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        if writer.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        let config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("eu-west-1"))
+            .endpoint_url(endpoint)
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new("test", "test", None, None, "stub")))
+            .build();
+        Self { config, requests }
+    }
+
+    // Buckets are addressed in the path, the stub has no DNS for virtual hosts
+    pub(crate) fn s3(&self) -> aws_sdk_s3::Client {
+        aws_sdk_s3::Client::from_conf(aws_sdk_s3::config::Builder::from(&self.config).force_path_style(true).build())
+    }
+
+    pub(crate) fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<StubRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok().filter(|read| *read > 0)?;
+    let mut parts = line.split_whitespace();
+    let (method, uri) = (parts.next()?.to_string(), parts.next()?.to_string());
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(StubRequest { method, uri, headers, body: String::from_utf8_lossy(&body).into_owned() })
+}
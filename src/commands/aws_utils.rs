@@ -1,7 +1,15 @@
 use crate::commands::ecs_connect::{AwsResource, ECSContainer};
+use crate::commands::cli_utils::{prompt_region, require_interactive};
+use crate::commands::ssm_session;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use aws_sdk_ec2 as ec2;
+use aws_sdk_ec2::types::Filter;
 use aws_sdk_ecs as ecs;
-use crossterm::style::Print;
+use aws_sdk_elasticache as elasticache;
+use aws_sdk_rds as rds;
 
 #[derive(Debug)]
 pub struct EC2Instance {
@@ -9,16 +17,233 @@ pub struct EC2Instance {
     pub(crate) name: String,
 }
 
-pub(crate) async fn ecs_execute_command(cluster: &str, task: &str, container: &str, command: &str) {
-    ctrlc::set_handler(move || {}).expect("Error setting Ctrl-C handler");
-    let command = format!("aws ecs execute-command --cluster {} --task {} --container {} --command '{}' --interactive", cluster, task, container, command);
-    println!("{}", command);
-    let output = std::process::Command::new("/bin/sh")
-        .arg("-c")
-        .arg(command)
-        .spawn()
-        .expect("failed to execute process");
-    let _ = output.wait_with_output();
+// Database endpoint to port forward to, `id` is the RDS/ElastiCache identifier
+#[derive(Debug, Clone)]
+pub(crate) struct DatabaseEndpoint {
+    pub(crate) id: String,
+    // writer, reader or custom for clusters, primary, reader or configuration for ElastiCache
+    pub(crate) role: String,
+    pub(crate) name: String,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+// Every AWS client is built from this config so that --profile and --region apply everywhere
+pub(crate) async fn load_config(matches: &clap::ArgMatches) -> SdkConfig {
+    let region = matches.get_one::<String>("region").map(|r| r.as_str());
+    load_config_in_region(matches, region).await
+}
+
+pub(crate) async fn load_config_in_region(matches: &clap::ArgMatches, region: Option<&str>) -> SdkConfig {
+    let profile = matches.get_one::<String>("profile").map(|p| p.as_str());
+    load_profile_config(profile, region).await
+}
+
+// --region, else the region of the profile or AWS_REGION, else a prompt
+pub(crate) async fn resolve_region(matches: &clap::ArgMatches) -> String {
+    if let Some(region) = matches.get_one::<String>("region") {
+        return region.clone();
+    }
+    if let Some(region) = load_config(matches).await.region() {
+        return region.to_string();
+    }
+    require_interactive("region");
+    prompt_region()
+}
+
+pub(crate) async fn load_profile_config(profile: Option<&str>, region: Option<&str>) -> SdkConfig {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(profile) = profile {
+        loader = loader.profile_name(profile);
+    }
+    if let Some(region) = region {
+        loader = loader.region(Region::new(region.to_string()));
+    }
+    loader.load().await
+}
+
+// S3 clients per bucket region, using a client of another region fails with a redirect
+#[derive(Clone)]
+pub(crate) struct S3Clients {
+    config: SdkConfig,
+    clients: Arc<Mutex<HashMap<String, aws_sdk_s3::Client>>>,
+    regions: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl S3Clients {
+    pub(crate) fn new(config: &SdkConfig) -> Self {
+        Self {
+            config: config.clone(),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            regions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Client of the configured region, for calls that aren't tied to a bucket
+    pub(crate) fn default_client(&self) -> aws_sdk_s3::Client {
+        aws_sdk_s3::Client::new(&self.config)
+    }
+
+    pub(crate) fn for_region(&self, region: &str) -> aws_sdk_s3::Client {
+        let mut clients = self.clients.lock().unwrap();
+        clients
+            .entry(region.to_string())
+            .or_insert_with(|| {
+                let config = aws_sdk_s3::config::Builder::from(&self.config)
+                    .region(Region::new(region.to_string()))
+                    .build();
+                aws_sdk_s3::Client::from_conf(config)
+            })
+            .clone()
+    }
+
+    pub(crate) async fn bucket_region(&self, bucket: &str) -> Option<String> {
+        if let Some(region) = self.regions.lock().unwrap().get(bucket) {
+            return Some(region.clone());
+        }
+
+        let client = self.default_client();
+        let region = match client.get_bucket_location().bucket(bucket).send().await {
+            // Buckets of us-east-1 have no location constraint, EU is the legacy name of eu-west-1
+            Ok(location) => match location.location_constraint().map(|l| l.as_str()) {
+                None | Some("") => Some("us-east-1".to_string()),
+                Some("EU") => Some("eu-west-1".to_string()),
+                Some(region) => Some(region.to_string()),
+            },
+            // Without s3:GetBucketLocation, the region is still sent back by HeadBucket
+            Err(_) => match client.head_bucket().bucket(bucket).send().await {
+                Ok(head) => head.bucket_region().map(|r| r.to_string()),
+                Err(error) => error
+                    .raw_response()
+                    .and_then(|r| r.headers().get("x-amz-bucket-region"))
+                    .map(|r| r.to_string()),
+            },
+        }?;
+
+        self.regions.lock().unwrap().insert(bucket.to_string(), region.clone());
+        Some(region)
+    }
+
+    pub(crate) async fn for_bucket(&self, bucket: &str) -> aws_sdk_s3::Client {
+        match self.bucket_region(bucket).await {
+            Some(region) => self.for_region(&region),
+            None => self.default_client(),
+        }
+    }
+}
+
+// Prefer the service message, fall back to the full error for network/credentials failures
+pub(crate) fn error_message<E: ProvideErrorMetadata + std::fmt::Debug>(error: &E) -> String {
+    match error.message() {
+        Some(message) => message.to_string(),
+        None => format!("{:?}", error),
+    }
+}
+
+// Quote the arguments that the shell would split or expand, to print a command that can be copied
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn find_in_path(program: &str) -> bool {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&paths).any(|dir| {
+        dir.join(program).is_file() || dir.join(format!("{}.exe", program)).is_file()
+    })
+}
+
+// SSM sessions go through the aws CLI, which hands the websocket over to the session-manager-plugin
+pub(crate) fn check_session_manager() -> Result<(), String> {
+    if !find_in_path("aws") {
+        return Err("the aws CLI is not installed (https://docs.aws.amazon.com/cli/latest/userguide/getting-started-install.html)".to_string());
+    }
+    if !find_in_path("session-manager-plugin") {
+        return Err("the session-manager-plugin is not installed (https://docs.aws.amazon.com/systems-manager/latest/userguide/session-manager-working-with-install-plugin.html)".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn aws_cli_command(args: &[String]) -> std::process::Command {
+    let mut command = std::process::Command::new("aws");
+    command.args(args);
+    command
+}
+
+// Run the aws CLI in the foreground and exit with its status. Ctrl-C goes to the session,
+// not to us
+pub(crate) fn run_aws_cli(args: &[String]) -> ! {
+    if let Err(error) = check_session_manager() {
+        println!("Can't start the session: {}", error);
+        std::process::exit(127);
+    }
+    let _ = ctrlc::set_handler(move || {});
+
+    let printed: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+    println!("aws {}", printed.join(" "));
+
+    let status = match aws_cli_command(args).status() {
+        Ok(status) => status,
+        Err(error) => {
+            println!("Failed to run the aws CLI: {}", error);
+            std::process::exit(127);
+        }
+    };
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+// Sessions run with the built-in Session Manager client unless --aws-cli is set
+pub(crate) fn use_aws_cli(matches: &clap::ArgMatches) -> bool {
+    matches.get_flag("aws-cli")
+}
+
+// The aws CLI doesn't read our config, pass --profile and --region along
+pub(crate) fn aws_cli_args(matches: &clap::ArgMatches, mut args: Vec<String>) -> Vec<String> {
+    for name in ["profile", "region"] {
+        if let Some(value) = matches.get_one::<String>(name) {
+            args.push(format!("--{}", name));
+            args.push(value.clone());
+        }
+    }
+    args
+}
+
+pub(crate) fn run_aws_cli_with(matches: &clap::ArgMatches, args: Vec<String>) -> ! {
+    run_aws_cli(&aws_cli_args(matches, args));
+}
+
+pub(crate) async fn ecs_execute_command(
+    matches: &clap::ArgMatches,
+    cluster: &str,
+    task: &str,
+    container: &str,
+    command: &str,
+) {
+    if !use_aws_cli(matches) {
+        let config = load_config(matches).await;
+        let session = ssm_session::start_ecs_session(&config, cluster, task, container, command).await;
+        ssm_session::exit_with_shell(&config, session).await;
+    }
+    let args: Vec<String> = vec![
+        "ecs".into(),
+        "execute-command".into(),
+        "--cluster".into(),
+        cluster.into(),
+        "--task".into(),
+        task.into(),
+        "--container".into(),
+        container.into(),
+        "--command".into(),
+        command.into(),
+        "--interactive".into(),
+    ];
+    run_aws_cli_with(matches, args);
 }
 
 pub(crate) async fn list_ec2_instances(client: &ec2::Client) -> Vec<EC2Instance> {
@@ -36,15 +261,16 @@ pub(crate) async fn list_ec2_instances(client: &ec2::Client) -> Vec<EC2Instance>
             }
 
             let instance_id = instance.instance_id.clone().unwrap();
-            let name = instance
+            // Instances without a Name tag aren't listed
+            let Some(name) = instance
                 .tags
-                .unwrap()
-                .iter()
+                .unwrap_or_default()
+                .into_iter()
                 .find(|tag| tag.key.as_deref() == Some("Name"))
-                .unwrap()
-                .value
-                .clone()
-                .unwrap();
+                .and_then(|tag| tag.value)
+            else {
+                continue;
+            };
             let display_name = format!("{} ({})", name, instance_id);
             res.push(EC2Instance {
                 instance_id,
@@ -55,6 +281,240 @@ pub(crate) async fn list_ec2_instances(client: &ec2::Client) -> Vec<EC2Instance>
     res
 }
 
+// RDS instances, Aurora/RDS cluster endpoints (writer, reader, custom) and ElastiCache replication groups
+pub(crate) async fn list_database_endpoints(config: &SdkConfig) -> Vec<DatabaseEndpoint> {
+    let (clusters, instances, caches) = tokio::join!(
+        list_rds_cluster_endpoints(config),
+        list_rds_instance_endpoints(config),
+        list_elasticache_endpoints(config),
+    );
+    clusters.into_iter().chain(instances).chain(caches).collect()
+}
+
+fn endpoint_port(port: Option<i32>) -> Option<u16> {
+    port.and_then(|port| u16::try_from(port).ok())
+}
+
+async fn list_rds_cluster_endpoints(config: &SdkConfig) -> Vec<DatabaseEndpoint> {
+    let client = rds::Client::new(config);
+    let clusters = client.describe_db_clusters().into_paginator().items().send().collect::<Result<Vec<_>, _>>().await;
+    let clusters = match clusters {
+        Ok(clusters) => clusters,
+        Err(error) => {
+            println!("Error listing RDS clusters: {}", error.message().unwrap_or("unknown error"));
+            return vec![];
+        }
+    };
+
+    let mut res = Vec::new();
+    for cluster in clusters {
+        let (Some(id), Some(port)) = (cluster.db_cluster_identifier(), endpoint_port(cluster.port())) else {
+            continue;
+        };
+        let engine = cluster.engine().unwrap_or_default();
+        let kind = if engine.starts_with("aurora") { "Aurora" } else { "RDS cluster" };
+        let mut hosts: Vec<(&str, &str)> = Vec::new();
+        if let Some(host) = cluster.endpoint() {
+            hosts.push(("writer", host));
+        }
+        if let Some(host) = cluster.reader_endpoint() {
+            hosts.push(("reader", host));
+        }
+        hosts.extend(cluster.custom_endpoints().iter().map(|host| ("custom", host.as_str())));
+        for (role, host) in hosts {
+            res.push(DatabaseEndpoint {
+                id: id.to_string(),
+                role: role.to_string(),
+                name: format!("{} {} {} ({})", kind, id, role, engine),
+                host: host.to_string(),
+                port,
+            });
+        }
+    }
+    res
+}
+
+async fn list_rds_instance_endpoints(config: &SdkConfig) -> Vec<DatabaseEndpoint> {
+    let client = rds::Client::new(config);
+    let instances = client.describe_db_instances().into_paginator().items().send().collect::<Result<Vec<_>, _>>().await;
+    let instances = match instances {
+        Ok(instances) => instances,
+        Err(error) => {
+            println!("Error listing RDS instances: {}", error.message().unwrap_or("unknown error"));
+            return vec![];
+        }
+    };
+
+    let mut res = Vec::new();
+    for instance in instances {
+        let Some(id) = instance.db_instance_identifier() else { continue };
+        let Some(endpoint) = instance.endpoint() else { continue };
+        let (Some(host), Some(port)) = (endpoint.address(), endpoint_port(endpoint.port())) else {
+            continue;
+        };
+        res.push(DatabaseEndpoint {
+            id: id.to_string(),
+            role: "instance".to_string(),
+            name: format!("RDS {} ({})", id, instance.engine().unwrap_or_default()),
+            host: host.to_string(),
+            port,
+        });
+    }
+    res
+}
+
+async fn list_elasticache_endpoints(config: &SdkConfig) -> Vec<DatabaseEndpoint> {
+    let client = elasticache::Client::new(config);
+    let groups = client.describe_replication_groups().into_paginator().items().send().collect::<Result<Vec<_>, _>>().await;
+    let groups = match groups {
+        Ok(groups) => groups,
+        Err(error) => {
+            println!("Error listing ElastiCache replication groups: {}", error.message().unwrap_or("unknown error"));
+            return vec![];
+        }
+    };
+
+    let mut res = Vec::new();
+    for group in groups {
+        let Some(id) = group.replication_group_id() else { continue };
+        // Cluster mode has a single configuration endpoint, otherwise each node group has a primary and a reader
+        let mut endpoints = Vec::new();
+        if let Some(endpoint) = group.configuration_endpoint() {
+            endpoints.push(("configuration", endpoint));
+        }
+        for node_group in group.node_groups() {
+            if let Some(endpoint) = node_group.primary_endpoint() {
+                endpoints.push(("primary", endpoint));
+            }
+            if let Some(endpoint) = node_group.reader_endpoint() {
+                endpoints.push(("reader", endpoint));
+            }
+        }
+        for (role, endpoint) in endpoints {
+            let (Some(host), Some(port)) = (endpoint.address(), endpoint_port(endpoint.port())) else {
+                continue;
+            };
+            res.push(DatabaseEndpoint {
+                id: id.to_string(),
+                role: role.to_string(),
+                name: format!("ElastiCache {} {} ({})", id, role, group.engine().unwrap_or("redis")),
+                host: host.to_string(),
+                port,
+            });
+        }
+    }
+    res
+}
+
+// Accept either an instance ID or the value of its Name tag
+pub(crate) async fn find_ec2_instance(client: &ec2::Client, id_or_name: &str) -> Option<String> {
+    if id_or_name.starts_with("i-") {
+        return Some(id_or_name.to_string());
+    }
+    list_ec2_instances(client)
+        .await
+        .into_iter()
+        .find(|i| i.name == format!("{} ({})", id_or_name, i.instance_id))
+        .map(|i| i.instance_id)
+}
+
+// First running instance carrying the tag
+pub(crate) async fn find_ec2_instance_by_tag(client: &ec2::Client, key: &str, value: &str) -> Option<String> {
+    let output = client
+        .describe_instances()
+        .filters(Filter::builder().name(format!("tag:{}", key)).values(value).build())
+        .filters(Filter::builder().name("instance-state-name").values("running").build())
+        .send()
+        .await
+        .ok()?;
+    output
+        .reservations()
+        .iter()
+        .flat_map(|r| r.instances())
+        .find_map(|i| i.instance_id().map(|id| id.to_string()))
+}
+
+pub(crate) enum Target {
+    // Instance ID, or an SSM target given as is
+    Ssm(String),
+    Container {
+        cluster: String,
+        task: String,
+        container: String,
+        runtime_id: String,
+    },
+}
+
+impl Target {
+    pub(crate) fn ssm_target(&self) -> String {
+        match self {
+            Target::Ssm(target) => target.clone(),
+            Target::Container { cluster, task, runtime_id, .. } => format!("ecs:{}_{}_{}", cluster, task, runtime_id),
+        }
+    }
+}
+
+// Targets of profiles and forwards, looked up again for every session:
+//   i-..., mi-..., ecs:<cluster>_<task>_<runtime_id>  used as is
+//   tag:<key>=<value>                                 first running instance with the tag
+//   ecs-service:<cluster>/<service>[/<container>]     a running task of the service
+//   ecs-task:<cluster>/<task>[/<container>]
+//   anything else                                     Name tag of an instance
+pub(crate) async fn resolve_target(config: &SdkConfig, target: &str) -> Result<Target, String> {
+    if target.starts_with("ecs:") || target.starts_with("i-") || target.starts_with("mi-") {
+        return Ok(Target::Ssm(target.to_string()));
+    }
+    if let Some(tag) = target.strip_prefix("tag:") {
+        let (key, value) = tag.split_once('=').ok_or(format!("invalid tag {}, expected Key=Value", tag))?;
+        let client = ec2::Client::new(config);
+        return find_ec2_instance_by_tag(&client, key, value)
+            .await
+            .map(Target::Ssm)
+            .ok_or(format!("no running instance tagged {}", tag));
+    }
+    let (ecs_target, by_service) = match (target.strip_prefix("ecs-service:"), target.strip_prefix("ecs-task:")) {
+        (Some(service), _) => (service, true),
+        (_, Some(task)) => (task, false),
+        _ => {
+            let client = ec2::Client::new(config);
+            return find_ec2_instance(&client, target)
+                .await
+                .map(Target::Ssm)
+                .ok_or(format!("no running instance found for {}", target));
+        }
+    };
+
+    let mut parts = ecs_target.splitn(3, '/');
+    let (Some(cluster), Some(name)) = (parts.next(), parts.next()) else {
+        return Err(format!("invalid ECS target {}, expected <cluster>/<service or task>[/<container>]", target));
+    };
+    let container = parts.next();
+    let client = ecs::Client::new(config);
+    let task = if by_service {
+        match list_service_tasks(&client, cluster, name).await.first() {
+            Some(task) => task.name.clone(),
+            None => return Err(format!("no running task found for service {}", name)),
+        }
+    } else {
+        name.to_string()
+    };
+    let containers = list_task_container(&client, cluster, &task).await;
+    let found = match container {
+        Some(container) => containers.into_iter().find(|c| c.name == container),
+        None if containers.len() == 1 => containers.into_iter().next(),
+        None => return Err(format!("task {} has {} running containers, set the container", task, containers.len())),
+    };
+    let Some(found) = found else {
+        return Err(format!("no running container {} in task {}", container.unwrap_or_default(), task));
+    };
+    Ok(Target::Container {
+        cluster: cluster.to_string(),
+        task,
+        container: found.name,
+        runtime_id: found.runtime_id,
+    })
+}
+
 pub(crate) async fn list_task_container(
     client: &ecs::Client,
     cluster: &str,
@@ -74,7 +534,7 @@ pub(crate) async fn list_task_container(
 
     for container in containers.unwrap().tasks.unwrap().clone() {
         for container in container.containers.unwrap().clone() {
-            if container.runtime_id == None { continue }
+            if container.runtime_id.is_none() { continue }
             let container_name = container.name.clone().unwrap();
             res.push(ECSContainer {
                 name: container_name,
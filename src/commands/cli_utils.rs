@@ -1,4 +1,46 @@
+use clap::ArgMatches;
 use promkit::preset::listbox::Listbox;
+use promkit::preset::readline::Readline;
+use promkit::suggest::Suggest;
+use std::io::IsTerminal;
+
+// Suggestions of the region prompt, any other region name is accepted
+const AWS_REGIONS: [&str; 34] = [
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+    "af-south-1",
+    "ap-east-1",
+    "ap-south-1",
+    "ap-south-2",
+    "ap-northeast-1",
+    "ap-northeast-2",
+    "ap-northeast-3",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "ap-southeast-3",
+    "ap-southeast-4",
+    "ap-southeast-5",
+    "ca-central-1",
+    "ca-west-1",
+    "eu-central-1",
+    "eu-central-2",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "eu-south-1",
+    "eu-south-2",
+    "eu-north-1",
+    "il-central-1",
+    "me-south-1",
+    "me-central-1",
+    "sa-east-1",
+    "cn-north-1",
+    "cn-northwest-1",
+    "us-gov-east-1",
+    "us-gov-west-1",
+];
 
 pub(crate) fn select_type() -> String {
     let types = vec!["EC2", "ECS container"];
@@ -11,6 +53,70 @@ pub(crate) fn select_type() -> String {
         .unwrap()
 }
 
-pub(crate) fn get_index_of<T: PartialEq>(vec: &Vec<T>, value: T) -> usize {
+pub(crate) fn get_index_of<T: PartialEq>(vec: &[T], value: T) -> usize {
     vec.iter().position(|x| *x == value).unwrap()
 }
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.2} {}", size, units[unit])
+    }
+}
+
+pub(crate) fn is_interactive() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+// Exit when a value is missing and we can't prompt for it (CI, pipes, scripts...)
+pub(crate) fn require_interactive(flag: &str) {
+    if !is_interactive() {
+        println!(
+            "Missing --{} argument and stdin is not a terminal, can't prompt for it",
+            flag
+        );
+        std::process::exit(1);
+    }
+}
+
+pub(crate) fn arg_or_prompt<F: FnOnce() -> String>(
+    matches: &ArgMatches,
+    name: &str,
+    prompt: F,
+) -> String {
+    match matches.get_one::<String>(name) {
+        Some(value) => value.clone(),
+        None => {
+            require_interactive(name);
+            prompt()
+        }
+    }
+}
+
+pub(crate) fn prompt_region() -> String {
+    let mut region = Readline::default()
+        .title("Which region should I use ? (Press tab to see the list of available regions)")
+        .enable_suggest(Suggest::from_iter(AWS_REGIONS))
+        .validator(
+            |text| !text.trim().is_empty(),
+            |text| format!("You should enter a region {}", text),
+        )
+        .prompt()
+        .unwrap();
+    let region_string = region.run();
+    match region_string {
+        Ok(value) => value.trim().to_string(),
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::commands::forward::ForwardSpec;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) delete_bucket: DeleteBucketConfig,
+    pub(crate) profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct DeleteBucketConfig {
+    // Buckets carrying this tag (with any value but "false") can't be deleted
+    pub(crate) protection_tag: String,
+    // Glob patterns of bucket names that can't be deleted
+    pub(crate) protected_patterns: Vec<String>,
+}
+
+impl Default for DeleteBucketConfig {
+    fn default() -> Self {
+        Self {
+            protection_tag: "devops-cli:protected".to_string(),
+            protected_patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProfileType {
+    Ec2,
+    Ecs,
+}
+
+// Saved connection or port forward, the target is looked up when the profile is used
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    #[serde(rename = "type")]
+    pub(crate) kind: ProfileType,
+    // EC2: instance ID or Name tag, or a Key=Value tag
+    pub(crate) instance: Option<String>,
+    pub(crate) tag: Option<String>,
+    // ECS: a task of the service, or the given task
+    pub(crate) cluster: Option<String>,
+    pub(crate) service: Option<String>,
+    pub(crate) task: Option<String>,
+    pub(crate) container: Option<String>,
+    // Port forward, a shell is started without remote_port
+    pub(crate) host: Option<String>,
+    pub(crate) remote_port: Option<u16>,
+    pub(crate) local_port: Option<u16>,
+    // Shell command of ECS containers
+    pub(crate) command: Option<String>,
+}
+
+impl Profile {
+    // Target in the format of aws_utils::resolve_target
+    pub(crate) fn target(&self) -> Result<String, String> {
+        match self.kind {
+            ProfileType::Ec2 => match (&self.instance, &self.tag) {
+                (Some(instance), _) => Ok(instance.clone()),
+                (None, Some(tag)) => Ok(format!("tag:{}", tag)),
+                (None, None) => Err("set instance or tag".to_string()),
+            },
+            ProfileType::Ecs => {
+                let cluster = self.cluster.as_ref().ok_or("set cluster")?;
+                let mut target = match (&self.task, &self.service) {
+                    (Some(task), _) => format!("ecs-task:{}/{}", cluster, task),
+                    (None, Some(service)) => format!("ecs-service:{}/{}", cluster, service),
+                    (None, None) => return Err("set service or task".to_string()),
+                };
+                if let Some(container) = &self.container {
+                    target = format!("{}/{}", target, container);
+                }
+                Ok(target)
+            }
+        }
+    }
+
+    // The host defaults to the target itself and the local port to the remote one
+    pub(crate) fn forward_spec(&self) -> Result<ForwardSpec, String> {
+        let remote_port = self.remote_port.ok_or("set remote_port")?;
+        Ok(ForwardSpec {
+            target: self.target()?,
+            host: self.host.clone().unwrap_or_else(|| "localhost".to_string()),
+            remote_port,
+            local_port: self.local_port.unwrap_or(remote_port),
+        })
+    }
+}
+
+// $DEVOPS_CLI_CONFIG, or config.toml in $XDG_CONFIG_HOME/devops-cli (~/.config/devops-cli)
+pub(crate) fn config_path() -> PathBuf {
+    if let Ok(path) = std::env::var("DEVOPS_CLI_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let config_home = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".config"),
+    };
+    config_home.join("devops-cli").join("config.toml")
+}
+
+pub(crate) fn read_config() -> Config {
+    let path = config_path();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return Config::default(),
+    };
+    match toml::from_str(&content) {
+        Ok(config) => config,
+        Err(error) => {
+            println!("Invalid config file {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+    }
+}
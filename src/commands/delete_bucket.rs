@@ -1,33 +1,566 @@
-use aws_sdk_s3::error::SdkError;
-use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
-use promkit::preset::confirm::Confirm;
-use promkit::preset::listbox::Listbox;
+use crate::commands::aws_utils::{error_message, load_config, S3Clients};
+use crate::commands::cli_utils::{format_bytes, get_index_of, require_interactive};
+use crate::commands::config::{read_config, DeleteBucketConfig};
+use std::collections::BTreeMap;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::list_object_versions::ListObjectVersionsOutput;
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use glob::Pattern;
+use regex::Regex;
+use promkit::preset::checkbox::Checkbox;
+use promkit::preset::readline::Readline;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
 
-use aws_sdk_s3::operation::list_objects_v2::{ListObjectsV2Error, ListObjectsV2Output};
+// DeleteObjects accepts at most 1000 keys per request
+const DELETE_BATCH_SIZE: usize = 1000;
+const MAX_RETRIES: u32 = 8;
+const MAX_PRINTED_ERRORS: usize = 10;
 
-async fn empty_bucket(client: &aws_sdk_s3::Client, bucket_name: &str) {
-    let objects = list_objects(client, bucket_name).await.unwrap();
-    for object in objects {
-        for content in object.contents.unwrap() {
-            let key = content.key.unwrap();
-            let _ = client.delete_object().bucket(bucket_name).key(key).send();
+// Restricts deletion (or the dry-run report) to a part of the bucket
+#[derive(Default)]
+pub(crate) struct ObjectFilter {
+    pub(crate) prefix: Option<String>,
+    // Only objects last modified before this date (seconds since the epoch), from --older-than
+    pub(crate) modified_before: Option<i64>,
+    pub(crate) include: Vec<Pattern>,
+    pub(crate) exclude: Vec<Pattern>,
+}
+
+impl ObjectFilter {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        let patterns = |name: &str| -> Vec<Pattern> {
+            matches
+                .get_many::<String>(name)
+                .unwrap_or_default()
+                .map(|p| match Pattern::new(p) {
+                    Ok(pattern) => pattern,
+                    Err(error) => {
+                        println!("Invalid --{} pattern {}: {}", name, p, error);
+                        std::process::exit(1);
+                    }
+                })
+                .collect()
+        };
+        Self {
+            prefix: matches.get_one::<String>("prefix").cloned(),
+            modified_before: matches.get_one::<Duration>("older-than").map(|age| cutoff(*age)),
+            include: patterns("include"),
+            exclude: patterns("exclude"),
+        }
+    }
+
+    // Without any filter the whole bucket goes away, bucket included
+    pub(crate) fn is_empty(&self) -> bool {
+        self.prefix.is_none() && self.modified_before.is_none() && self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(&self, key: &str, last_modified: Option<&DateTime>) -> bool {
+        if let Some(prefix) = &self.prefix {
+            if !key.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(cutoff) = self.modified_before {
+            match last_modified {
+                Some(last_modified) if last_modified.secs() < cutoff => {}
+                _ => return false,
+            }
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|p| p.matches(key)) {
+            return false;
+        }
+        !self.exclude.iter().any(|p| p.matches(key))
+    }
+}
+
+// An age going back before 1970 leaves no object old enough
+fn cutoff(age: Duration) -> i64 {
+    SystemTime::now()
+        .checked_sub(age)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(i64::MIN, |since_epoch| since_epoch.as_secs() as i64)
+}
+
+// Parse ages such as 90s, 30m, 12h, 7d or 2w
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let unit = value.chars().last().ok_or("age can't be empty")?;
+    let number = value[..value.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .map_err(|_| format!("invalid age {}, expected something like 30d", value))?;
+    let seconds = match unit {
+        's' => Some(number),
+        'm' => number.checked_mul(60),
+        'h' => number.checked_mul(3600),
+        'd' => number.checked_mul(86400),
+        'w' => number.checked_mul(604800),
+        _ => return Err(format!("invalid unit {} in {}, use s, m, h, d or w", unit, value)),
+    };
+    seconds.map(Duration::from_secs).ok_or_else(|| format!("age {} is too large", value))
+}
+
+#[derive(Default)]
+struct Progress {
+    listed: AtomicUsize,
+    versions: AtomicUsize,
+    delete_markers: AtomicUsize,
+    deleted: AtomicUsize,
+    bytes: AtomicU64,
+    uploads: AtomicUsize,
+    errors: AtomicUsize,
+    messages: Mutex<Vec<String>>,
+}
+
+impl Progress {
+    fn error(&self, count: usize, message: String) {
+        self.errors.fetch_add(count, Ordering::Relaxed);
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() < MAX_PRINTED_ERRORS {
+            messages.push(message);
+        }
+    }
+
+    fn render(&self, started: Instant) -> String {
+        let listed = self.listed.load(Ordering::Relaxed);
+        let deleted = self.deleted.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+        let elapsed = started.elapsed().as_secs_f64().max(0.001);
+        let width = 30;
+        let filled = ((deleted + errors) * width).checked_div(listed).unwrap_or(0).min(width);
+        format!(
+            "[{}{}] {}/{} deleted | {:.0} objects/s | {} errors",
+            "#".repeat(filled),
+            ".".repeat(width - filled),
+            deleted,
+            listed,
+            deleted as f64 / elapsed,
+            errors
+        )
+    }
+}
+
+fn is_slow_down<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    match error {
+        SdkError::ServiceError(service_error) => service_error.err().code() == Some("SlowDown"),
+        _ => error.code() == Some("SlowDown"),
+    }
+}
+
+// What empty_bucket did, used for the summary of multi-bucket runs
+pub(crate) struct EmptyReport {
+    pub(crate) deleted: usize,
+    pub(crate) bytes: u64,
+    pub(crate) errors: usize,
+    pub(crate) complete: bool,
+}
+
+impl EmptyReport {
+    pub(crate) fn success(&self) -> bool {
+        self.complete && self.errors == 0
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(200 * 2u64.pow(attempt)).min(Duration::from_secs(20))
+}
+
+async fn delete_batch(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    mut objects: Vec<(ObjectIdentifier, u64)>,
+    progress: &Progress,
+) {
+    let mut attempt = 0;
+    loop {
+        let delete = Delete::builder()
+            .set_objects(Some(objects.iter().map(|(o, _)| o.clone()).collect()))
+            .quiet(true)
+            .build()
+            .unwrap();
+        let response = client
+            .delete_objects()
+            .bucket(bucket_name)
+            .delete(delete)
+            .send()
+            .await;
+        match response {
+            Ok(output) => {
+                // Throttled keys are retried, every other failure is final
+                let mut throttled: Vec<(ObjectIdentifier, u64)> = Vec::new();
+                let mut failed_bytes = 0;
+                for error in output.errors() {
+                    let failed = objects
+                        .iter()
+                        .find(|(o, _)| Some(o.key()) == error.key() && o.version_id() == error.version_id());
+                    failed_bytes += failed.map(|(_, size)| *size).unwrap_or_default();
+                    if error.code() == Some("SlowDown") && attempt < MAX_RETRIES {
+                        if let Some(failed) = failed {
+                            throttled.push(failed.clone());
+                        }
+                    } else {
+                        progress.error(
+                            1,
+                            format!(
+                                "Failed to delete {}: {}",
+                                error.key().unwrap_or_default(),
+                                error.message().unwrap_or_default()
+                            ),
+                        );
+                    }
+                }
+                progress
+                    .deleted
+                    .fetch_add(objects.len() - output.errors().len(), Ordering::Relaxed);
+                let bytes: u64 = objects.iter().map(|(_, size)| size).sum();
+                progress.bytes.fetch_add(bytes - failed_bytes, Ordering::Relaxed);
+                if throttled.is_empty() {
+                    return;
+                }
+                objects = throttled;
+            }
+            Err(error) if is_slow_down(&error) && attempt < MAX_RETRIES => {}
+            Err(error) => {
+                progress.error(objects.len(), format!("Failed to delete objects: {}", error_message(&error)));
+                return;
+            }
+        }
+        tokio::time::sleep(backoff(attempt)).await;
+        attempt += 1;
+    }
+}
+
+async fn list_versions_page(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    prefix: Option<&str>,
+    key_marker: Option<String>,
+    version_id_marker: Option<String>,
+) -> Option<ListObjectVersionsOutput> {
+    let mut attempt = 0;
+    loop {
+        let page = client
+            .list_object_versions()
+            .bucket(bucket_name)
+            .set_prefix(prefix.map(|p| p.to_string()))
+            .set_key_marker(key_marker.clone())
+            .set_version_id_marker(version_id_marker.clone())
+            .send()
+            .await;
+        match page {
+            Ok(page) => return Some(page),
+            Err(error) if is_slow_down(&error) && attempt < MAX_RETRIES => {
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(error) => {
+                println!("\nFailed to list object versions: {}", error_message(&error));
+                return None;
+            }
         }
     }
 }
 
-async fn list_objects(
+// List pages one after the other and delete each of them in the background, at most
+// `concurrency` DeleteObjects calls are in flight so memory stays bounded
+async fn delete_versions(
     client: &aws_sdk_s3::Client,
     bucket_name: &str,
-) -> Result<Vec<ListObjectsV2Output>, SdkError<ListObjectsV2Error, HttpResponse>> {
-    println!("Calling 'list_objects_v2 to pull objects to delete");
-    let paginator = client
-        .list_objects_v2()
-        .bucket(bucket_name)
-        .into_paginator()
-        .send();
-    paginator
-        .collect::<Result<Vec<ListObjectsV2Output>, SdkError<ListObjectsV2Error, HttpResponse>>>()
-        .await
+    filter: &ObjectFilter,
+    concurrency: u32,
+    progress: &Arc<Progress>,
+) -> bool {
+    let semaphore = Arc::new(Semaphore::new(concurrency as usize));
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+    let mut status = true;
+    loop {
+        let prefix = filter.prefix.as_deref();
+        let Some(page) = list_versions_page(client, bucket_name, prefix, key_marker, version_id_marker).await else {
+            status = false;
+            break;
+        };
+
+        let mut objects: Vec<(ObjectIdentifier, u64)> = Vec::new();
+        let versions: Vec<_> = page
+            .versions()
+            .iter()
+            .filter(|v| filter.matches(v.key().unwrap_or_default(), v.last_modified()))
+            .collect();
+        let delete_markers: Vec<_> = page
+            .delete_markers()
+            .iter()
+            .filter(|m| filter.matches(m.key().unwrap_or_default(), m.last_modified()))
+            .collect();
+        for version in &versions {
+            objects.push((
+                ObjectIdentifier::builder()
+                    .key(version.key().unwrap_or_default())
+                    .set_version_id(version.version_id().map(|v| v.to_string()))
+                    .build()
+                    .unwrap(),
+                version.size().unwrap_or_default() as u64,
+            ));
+        }
+        progress.versions.fetch_add(versions.len(), Ordering::Relaxed);
+        for marker in &delete_markers {
+            objects.push((
+                ObjectIdentifier::builder()
+                    .key(marker.key().unwrap_or_default())
+                    .set_version_id(marker.version_id().map(|v| v.to_string()))
+                    .build()
+                    .unwrap(),
+                0,
+            ));
+        }
+        progress.delete_markers.fetch_add(delete_markers.len(), Ordering::Relaxed);
+        progress.listed.fetch_add(objects.len(), Ordering::Relaxed);
+
+        for batch in objects.chunks(DELETE_BATCH_SIZE) {
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            let client = client.clone();
+            let bucket_name = bucket_name.to_string();
+            let batch = batch.to_vec();
+            let progress = progress.clone();
+            tokio::spawn(async move {
+                delete_batch(&client, &bucket_name, batch, &progress).await;
+                drop(permit);
+            });
+        }
+
+        if !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().map(|k| k.to_string());
+        version_id_marker = page.next_version_id_marker().map(|v| v.to_string());
+    }
+
+    // Wait for the in-flight deletions
+    let _ = semaphore.acquire_many(concurrency).await.unwrap();
+    status
+}
+
+async fn abort_multipart_uploads(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    filter: &ObjectFilter,
+    progress: &Progress,
+) -> bool {
+    let mut key_marker: Option<String> = None;
+    let mut upload_id_marker: Option<String> = None;
+    loop {
+        let page = client
+            .list_multipart_uploads()
+            .bucket(bucket_name)
+            .set_prefix(filter.prefix.clone())
+            .set_key_marker(key_marker.clone())
+            .set_upload_id_marker(upload_id_marker.clone())
+            .send()
+            .await;
+        let page = match page {
+            Ok(page) => page,
+            Err(error) => {
+                println!("Failed to list multipart uploads: {}", error_message(&error));
+                return false;
+            }
+        };
+
+        for upload in page.uploads() {
+            if !filter.matches(upload.key().unwrap_or_default(), upload.initiated()) {
+                continue;
+            }
+            let response = client
+                .abort_multipart_upload()
+                .bucket(bucket_name)
+                .key(upload.key().unwrap_or_default())
+                .upload_id(upload.upload_id().unwrap_or_default())
+                .send()
+                .await;
+            match response {
+                Ok(_) => {
+                    progress.uploads.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(error) => progress.error(
+                    1,
+                    format!(
+                        "Failed to abort upload of {}: {}",
+                        upload.key().unwrap_or_default(),
+                        error_message(&error)
+                    ),
+                ),
+            }
+        }
+
+        if !page.is_truncated().unwrap_or(false) {
+            return true;
+        }
+        key_marker = page.next_key_marker().map(|k| k.to_string());
+        upload_id_marker = page.next_upload_id_marker().map(|u| u.to_string());
+    }
+}
+
+pub(crate) async fn empty_bucket(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    filter: &ObjectFilter,
+    concurrency: u32,
+    live: bool,
+) -> EmptyReport {
+    let progress = Arc::new(Progress::default());
+    let started = Instant::now();
+    let done = Arc::new(AtomicBool::new(false));
+
+    println!("Deleting objects, versions and delete markers of {}", bucket_name);
+    let printer = {
+        let progress = progress.clone();
+        let done = done.clone();
+        let bucket_name = bucket_name.to_string();
+        // Redraw the bar in place on a terminal, log a line from time to time in CI or
+        // when several buckets are emptied at once
+        let live = live && std::io::stdout().is_terminal();
+        tokio::spawn(async move {
+            let mut last_line = Instant::now();
+            while !done.load(Ordering::Relaxed) {
+                if live {
+                    print!("\r{}", progress.render(started));
+                    let _ = std::io::stdout().flush();
+                } else if last_line.elapsed() >= Duration::from_secs(10) {
+                    println!("{}: {}", bucket_name, progress.render(started));
+                    last_line = Instant::now();
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+        })
+    };
+
+    let mut status = delete_versions(client, bucket_name, filter, concurrency, &progress).await;
+    status = status && abort_multipart_uploads(client, bucket_name, filter, &progress).await;
+
+    done.store(true, Ordering::Relaxed);
+    let _ = printer.await;
+    if live {
+        println!("\r{}", progress.render(started));
+    }
+
+    for message in progress.messages.lock().unwrap().iter() {
+        println!("{}: {}", bucket_name, message);
+    }
+    let errors = progress.errors.load(Ordering::Relaxed);
+    println!(
+        "{}: removed {} versions, {} delete markers and {} multipart uploads in {:.1}s ({} errors)",
+        bucket_name,
+        progress.versions.load(Ordering::Relaxed),
+        progress.delete_markers.load(Ordering::Relaxed),
+        progress.uploads.load(Ordering::Relaxed),
+        started.elapsed().as_secs_f64(),
+        errors
+    );
+    EmptyReport {
+        deleted: progress.deleted.load(Ordering::Relaxed),
+        bytes: progress.bytes.load(Ordering::Relaxed),
+        errors,
+        complete: status,
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    objects: usize,
+    bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+}
+
+// Group keys by their first folder below the --prefix
+fn top_prefix(key: &str, prefix: Option<&str>) -> String {
+    let prefix = prefix.unwrap_or_default();
+    let rest = key.strip_prefix(prefix).unwrap_or(key);
+    match rest.split_once('/') {
+        Some((folder, _)) => format!("{}{}/", prefix, folder),
+        None if prefix.is_empty() => "(root)".to_string(),
+        None => prefix.to_string(),
+    }
+}
+
+fn totals_table(title: &str, totals: &BTreeMap<String, Totals>) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![title, "Objects", "Size"]);
+    for (name, total) in totals {
+        table.add_row(vec![name.clone(), total.objects.to_string(), format_bytes(total.bytes)]);
+    }
+    table
+}
+
+// Enumerate what empty_bucket would delete without deleting anything
+async fn dry_run(client: &aws_sdk_s3::Client, bucket_name: &str, filter: &ObjectFilter) -> bool {
+    let mut by_prefix: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut by_storage_class: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut total = Totals::default();
+    let mut delete_markers = 0;
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+    let prefix = filter.prefix.as_deref();
+
+    println!("Listing objects, versions and delete markers of {}", bucket_name);
+    loop {
+        let Some(page) = list_versions_page(client, bucket_name, prefix, key_marker, version_id_marker).await else {
+            return false;
+        };
+
+        for version in page.versions() {
+            let key = version.key().unwrap_or_default();
+            if !filter.matches(key, version.last_modified()) {
+                continue;
+            }
+            let size = version.size().unwrap_or_default() as u64;
+            let storage_class = version
+                .storage_class()
+                .map(|c| c.as_str().to_string())
+                .unwrap_or("STANDARD".to_string());
+            by_prefix.entry(top_prefix(key, prefix)).or_default().add(size);
+            by_storage_class.entry(storage_class).or_default().add(size);
+            total.add(size);
+        }
+        for marker in page.delete_markers() {
+            if filter.matches(marker.key().unwrap_or_default(), marker.last_modified()) {
+                delete_markers += 1;
+            }
+        }
+
+        if !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().map(|k| k.to_string());
+        version_id_marker = page.next_version_id_marker().map(|v| v.to_string());
+    }
+
+    println!("{}", totals_table("Prefix", &by_prefix));
+    println!("{}", totals_table("Storage class", &by_storage_class));
+    println!(
+        "{} would delete {} versions ({}) and {} delete markers from {}",
+        "Dry run:".bold(),
+        total.objects,
+        format_bytes(total.bytes),
+        delete_markers,
+        bucket_name
+    );
+    true
 }
 
 async fn list_buckets(client: &aws_sdk_s3::Client) -> Vec<String> {
@@ -39,21 +572,65 @@ async fn list_buckets(client: &aws_sdk_s3::Client) -> Vec<String> {
     res
 }
 
-pub async fn delete_bucket() {
-    let config = aws_config::load_from_env().await;
-    let client = aws_sdk_s3::Client::new(&config);
+// Resolve every region up front so the picker can show it next to the name
+async fn bucket_regions(clients: &S3Clients, buckets: &[String]) -> Vec<String> {
+    let mut tasks = Vec::new();
+    for bucket in buckets {
+        let clients = clients.clone();
+        let bucket = bucket.clone();
+        tasks.push(tokio::spawn(async move { clients.bucket_region(&bucket).await }));
+    }
+    let mut regions = Vec::new();
+    for task in tasks {
+        regions.push(task.await.unwrap().unwrap_or("unknown region".to_string()));
+    }
+    regions
+}
 
-    let buckets_names = list_buckets(&client).await;
-    let bucket = Listbox::new(&buckets_names)
-        .title("Which bucket do you want to delete?")
-        .listbox_lines(5)
+async fn prompt_buckets(clients: &S3Clients, buckets_names: &[String]) -> Vec<String> {
+    let regions = bucket_regions(clients, buckets_names).await;
+    let labels: Vec<String> = buckets_names
+        .iter()
+        .zip(regions.iter())
+        .map(|(name, region)| format!("{} ({})", name, region))
+        .collect();
+    let selected = Checkbox::new(&labels)
+        .title("Which buckets do you want to delete? (Space to select, Enter to validate)")
+        .checkbox_lines(10)
         .prompt()
         .unwrap()
         .run()
         .unwrap();
+    selected
+        .into_iter()
+        .map(|label| buckets_names[get_index_of(&labels, label)].clone())
+        .collect()
+}
 
-    let confirmation_text = format!("Are you sure that you want to delete {} ?", bucket);
-    let mut confirm = Confirm::new(confirmation_text).prompt().unwrap();
+// Like GitHub repositories, the full bucket name has to be typed to confirm. With
+// several buckets, the number of buckets has to be typed instead
+pub(crate) fn confirm_deletion(buckets: &[String], filter: &ObjectFilter) -> bool {
+    let what = if filter.is_empty() { "" } else { "the matching objects of " };
+    let (confirmation_text, expected) = if buckets.len() == 1 {
+        (
+            format!(
+                "This will permanently delete {}{}. Type the bucket name to confirm",
+                what, buckets[0]
+            ),
+            buckets[0].clone(),
+        )
+    } else {
+        (
+            format!(
+                "This will permanently delete {}{} buckets. Type \"delete {} buckets\" to confirm",
+                what,
+                buckets.len(),
+                buckets.len()
+            ),
+            format!("delete {} buckets", buckets.len()),
+        )
+    };
+    let mut confirm = Readline::default().title(confirmation_text).prompt().unwrap();
     let confirm_string = confirm.run();
     let confirm_string = match confirm_string {
         Ok(value) => value,
@@ -63,12 +640,402 @@ pub async fn delete_bucket() {
         }
     };
     drop(confirm);
-    let status = confirm_string == "yes" || confirm_string == "y";
-    if !status {
-        println!("Aborted by user");
+    confirm_string.trim() == expected
+}
+
+// Versions past the first TFSTATE_SCAN_LIMIT are not checked, listing a whole log bucket would take hours
+const TFSTATE_SCAN_LIMIT: usize = 100_000;
+
+enum TfstateScan {
+    Found,
+    NotFound,
+    // The limit was reached before the end of the bucket
+    Unfinished,
+}
+
+// Versions are listed rather than objects: a deleted state still has versions that the deletion purges
+async fn contains_tfstate(client: &aws_sdk_s3::Client, bucket_name: &str) -> Result<TfstateScan, String> {
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+    let mut scanned = 0;
+    loop {
+        let page = client
+            .list_object_versions()
+            .bucket(bucket_name)
+            .set_key_marker(key_marker.clone())
+            .set_version_id_marker(version_id_marker.clone())
+            .send()
+            .await
+            .map_err(|error| error_message(&error))?;
+        let keys = page
+            .versions()
+            .iter()
+            .map(|v| v.key())
+            .chain(page.delete_markers().iter().map(|m| m.key()));
+        for key in keys {
+            if key.unwrap_or_default().ends_with(".tfstate") {
+                return Ok(TfstateScan::Found);
+            }
+            scanned += 1;
+        }
+        if !page.is_truncated().unwrap_or(false) {
+            return Ok(TfstateScan::NotFound);
+        }
+        if scanned >= TFSTATE_SCAN_LIMIT {
+            return Ok(TfstateScan::Unfinished);
+        }
+        key_marker = page.next_key_marker().map(|k| k.to_string());
+        version_id_marker = page.next_version_id_marker().map(|v| v.to_string());
+    }
+}
+
+// Protection set by the user: a protected name pattern or the protection tag
+pub(crate) async fn configured_protection(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    config: &DeleteBucketConfig,
+) -> Option<String> {
+    for pattern in &config.protected_patterns {
+        match Pattern::new(pattern) {
+            Ok(glob) if glob.matches(bucket_name) => {
+                return Some(format!("it matches the protected pattern {}", pattern));
+            }
+            Ok(_) => {}
+            Err(error) => return Some(format!("invalid protected pattern {}: {}", pattern, error)),
+        }
+    }
+
+    match client.get_bucket_tagging().bucket(bucket_name).send().await {
+        Ok(tagging) => {
+            let tag = tagging.tag_set().iter().find(|t| t.key() == config.protection_tag);
+            if let Some(tag) = tag {
+                if !tag.value().eq_ignore_ascii_case("false") {
+                    return Some(format!("it is tagged {}={}", tag.key(), tag.value()));
+                }
+            }
+        }
+        Err(error) if error.code() == Some("NoSuchTagSet") => {}
+        Err(error) => return Some(format!("its tags can't be read: {}", error_message(&error))),
+    }
+    None
+}
+
+// Returns why the bucket must not be deleted, if it is protected
+// `allow_unverified` accepts buckets too large to be fully checked for terraform states
+pub(crate) async fn protection_reason(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    config: &DeleteBucketConfig,
+    allow_unverified: bool,
+) -> Option<String> {
+    if let Some(reason) = configured_protection(client, bucket_name, config).await {
+        return Some(reason);
+    }
+    match contains_tfstate(client, bucket_name).await {
+        Ok(TfstateScan::Found) => Some("it contains terraform state files (*.tfstate)".to_string()),
+        Ok(TfstateScan::NotFound) => None,
+        Ok(TfstateScan::Unfinished) if allow_unverified => None,
+        Ok(TfstateScan::Unfinished) => Some(format!(
+            "couldn't verify the bucket holds no .tfstate, only its first {} object versions were checked (use --allow-unverified to delete it anyway)",
+            TFSTATE_SCAN_LIMIT
+        )),
+        Err(error) => Some(format!("its objects can't be listed: {}", error)),
+    }
+}
+
+struct BucketResult {
+    name: String,
+    region: String,
+    error: Option<String>,
+    deleted: usize,
+    bytes: u64,
+}
+
+async fn delete_one_bucket(
+    clients: &S3Clients,
+    bucket: &str,
+    filter: &ObjectFilter,
+    concurrency: u32,
+    live: bool,
+) -> BucketResult {
+    let client = clients.for_bucket(bucket).await;
+    let report = empty_bucket(&client, bucket, filter, concurrency, live).await;
+    let mut result = BucketResult {
+        name: bucket.to_string(),
+        region: clients.bucket_region(bucket).await.unwrap_or_default(),
+        error: None,
+        deleted: report.deleted,
+        bytes: report.bytes,
+    };
+    if !report.success() {
+        result.error = Some(format!("failed to empty the bucket ({} errors)", report.errors));
+        return result;
+    }
+
+    // A filtered run only purges part of the bucket, keep the bucket itself
+    if filter.is_empty() {
+        if let Err(error) = client.delete_bucket().bucket(bucket).send().await {
+            result.error = Some(error_message(&error));
+        }
+    }
+    result
+}
+
+fn summary_table(results: &[BucketResult], keep_buckets: bool) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Bucket", "Region", "Status", "Objects deleted", "Bytes freed"]);
+    for result in results {
+        let status = match &result.error {
+            None if keep_buckets => "Purged".to_string(),
+            None => "Deleted".to_string(),
+            Some(error) => format!("Failed: {}", error),
+        };
+        table.add_row(vec![
+            result.name.clone(),
+            result.region.clone(),
+            status,
+            result.deleted.to_string(),
+            format_bytes(result.bytes),
+        ]);
+    }
+    let deleted: usize = results.iter().map(|r| r.deleted).sum();
+    let bytes: u64 = results.iter().map(|r| r.bytes).sum();
+    let succeeded = results.iter().filter(|r| r.error.is_none()).count();
+    table.add_row(vec![
+        "Total".to_string(),
+        String::new(),
+        format!("{}/{} succeeded", succeeded, results.len()),
+        deleted.to_string(),
+        format_bytes(bytes),
+    ]);
+    table
+}
+
+async fn select_buckets(clients: &S3Clients, matches: &clap::ArgMatches) -> Vec<String> {
+    let client = clients.default_client();
+    let mut buckets: Vec<String> = matches
+        .get_many::<String>("bucket")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    if let Some(pattern) = matches.get_one::<String>("match") {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                println!("Invalid --match regex: {}", error);
+                std::process::exit(1);
+            }
+        };
+        for bucket in list_buckets(&client).await {
+            if regex.is_match(&bucket) && !buckets.contains(&bucket) {
+                buckets.push(bucket);
+            }
+        }
+        if buckets.is_empty() {
+            println!("No bucket matches {}", pattern);
+            std::process::exit(1);
+        }
+        return buckets;
+    }
+
+    if buckets.is_empty() {
+        require_interactive("bucket");
+        buckets = prompt_buckets(clients, &list_buckets(&client).await).await;
+    }
+    buckets
+}
+
+pub async fn delete_bucket(matches: &clap::ArgMatches) {
+    let config = load_config(matches).await;
+    let clients = S3Clients::new(&config);
+
+    let buckets = select_buckets(&clients, matches).await;
+    if buckets.is_empty() {
+        println!("No bucket selected");
+        return;
+    }
+
+    let filter = Arc::new(ObjectFilter::from_matches(matches));
+    if matches.get_flag("dry-run") {
+        let mut status = true;
+        for bucket in &buckets {
+            let client = clients.for_bucket(bucket).await;
+            status = dry_run(&client, bucket, &filter).await && status;
+        }
+        if !status {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Protected buckets are skipped and reported as failures in the summary
+    let protection = read_config().delete_bucket;
+    let mut results: Vec<BucketResult> = Vec::new();
+    let mut to_delete: Vec<String> = Vec::new();
+    for bucket in buckets {
+        let client = clients.for_bucket(&bucket).await;
+        match protection_reason(&client, &bucket, &protection, matches.get_flag("allow-unverified")).await {
+            Some(reason) => {
+                println!("{} refusing to delete {}: {}", "Protected:".red().bold(), bucket.bold(), reason);
+                results.push(BucketResult {
+                    region: clients.bucket_region(&bucket).await.unwrap_or_default(),
+                    name: bucket,
+                    error: Some(format!("protected, {}", reason)),
+                    deleted: 0,
+                    bytes: 0,
+                });
+            }
+            None => to_delete.push(bucket),
+        }
+    }
+
+    if !to_delete.is_empty() && !matches.get_flag("yes") {
+        require_interactive("yes");
+        if to_delete.len() > 1 {
+            println!("Buckets to delete:");
+            for bucket in &to_delete {
+                println!("  - {}", bucket);
+            }
+        }
+        if !confirm_deletion(&to_delete, &filter) {
+            println!("Aborted by user");
+            std::process::exit(1);
+        }
+    }
+
+    let concurrency = *matches.get_one::<u32>("concurrency").unwrap();
+    let parallel = *matches.get_one::<u32>("parallel").unwrap();
+    let live = to_delete.len() == 1;
+    let semaphore = Arc::new(Semaphore::new(parallel as usize));
+    let mut tasks = Vec::new();
+    for bucket in to_delete {
+        let clients = clients.clone();
+        let filter = filter.clone();
+        let semaphore = semaphore.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+            delete_one_bucket(&clients, &bucket, &filter, concurrency, live).await
+        }));
+    }
+    for task in tasks {
+        results.push(task.await.unwrap());
+    }
+
+    println!("{}", summary_table(&results, !filter.is_empty()));
+    if results.iter().any(|r| r.error.is_some()) {
         std::process::exit(1);
     }
+    println!("Buckets processed {}", "successfully".green().bold());
+}
 
-    println!("{:?}", bucket);
-    empty_bucket(&client, &bucket).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days_ago(days: i64) -> DateTime {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        DateTime::from_secs(now - days * 86400)
+    }
+
+    fn patterns(globs: &[&str]) -> Vec<Pattern> {
+        globs.iter().map(|glob| Pattern::new(glob).unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_age_units() {
+        assert_eq!(parse_age("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_age("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(43200)));
+        assert_eq!(parse_age("7d"), Ok(Duration::from_secs(604800)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(1209600)));
+        assert_eq!(parse_age("0d"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn parse_age_rejects_invalid_input() {
+        assert!(parse_age("").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("30").unwrap_err().contains("invalid unit"));
+        assert!(parse_age("3y").unwrap_err().contains("invalid unit"));
+        assert!(parse_age("-3d").is_err());
+        assert!(parse_age("1.5d").is_err());
+        assert!(parse_age("3é").unwrap_err().contains("invalid unit"));
+    }
+
+    #[test]
+    fn parse_age_rejects_overflow() {
+        assert!(parse_age(&format!("{}w", u64::MAX / 604800 + 1)).unwrap_err().contains("too large"));
+        assert!(parse_age(&format!("{}m", u64::MAX)).unwrap_err().contains("too large"));
+        assert_eq!(parse_age(&format!("{}s", u64::MAX)), Ok(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = ObjectFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches("any/key", None));
+    }
+
+    #[test]
+    fn filter_by_prefix() {
+        let filter = ObjectFilter { prefix: Some("logs/".to_string()), ..Default::default() };
+        assert!(!filter.is_empty());
+        assert!(filter.matches("logs/2024/app.log", None));
+        assert!(!filter.matches("data/logs/app.log", None));
+        assert!(!filter.matches("logs", None));
+    }
+
+    #[test]
+    fn filter_by_include_and_exclude() {
+        let filter = ObjectFilter {
+            include: patterns(&["*.log", "*.gz"]),
+            exclude: patterns(&["keep/*"]),
+            ..Default::default()
+        };
+        assert!(filter.matches("app.log", None));
+        assert!(filter.matches("archive/app.gz", None));
+        assert!(!filter.matches("app.txt", None));
+        assert!(!filter.matches("keep/app.log", None));
+
+        let filter = ObjectFilter { exclude: patterns(&["*.tfstate"]), ..Default::default() };
+        assert!(filter.matches("notes.txt", None));
+        assert!(!filter.matches("env/terraform.tfstate", None));
+    }
+
+    #[test]
+    fn filter_by_age() {
+        let filter = ObjectFilter { modified_before: Some(cutoff(parse_age("30d").unwrap())), ..Default::default() };
+        assert!(filter.matches("old", Some(&days_ago(31))));
+        assert!(!filter.matches("recent", Some(&days_ago(29))));
+        // Without a date the age is unknown, the object is kept
+        assert!(!filter.matches("unknown", None));
+    }
+
+    #[test]
+    fn age_before_1970_matches_nothing() {
+        let filter = ObjectFilter { modified_before: Some(cutoff(parse_age("3000w").unwrap())), ..Default::default() };
+        assert!(!filter.is_empty());
+        assert!(!filter.matches("old", Some(&DateTime::from_secs(0))));
+        assert!(!filter.matches("older", Some(&DateTime::from_secs(-86400))));
+    }
+
+    #[test]
+    fn filters_combine() {
+        let filter = ObjectFilter {
+            prefix: Some("logs/".to_string()),
+            modified_before: Some(cutoff(parse_age("7d").unwrap())),
+            include: patterns(&["*.log"]),
+            exclude: patterns(&["*/audit.log"]),
+        };
+        assert!(filter.matches("logs/app.log", Some(&days_ago(8))));
+        assert!(!filter.matches("logs/app.log", Some(&days_ago(6))));
+        assert!(!filter.matches("data/app.log", Some(&days_ago(8))));
+        assert!(!filter.matches("logs/app.txt", Some(&days_ago(8))));
+        assert!(!filter.matches("logs/audit.log", Some(&days_ago(8))));
+    }
 }
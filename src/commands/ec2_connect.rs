@@ -1,4 +1,6 @@
-use crate::commands::aws_utils::{list_ec2_instances};
+use crate::commands::aws_utils::{find_ec2_instance, list_ec2_instances, load_config, run_aws_cli_with, use_aws_cli};
+use crate::commands::ssm_session;
+use crate::commands::cli_utils::require_interactive;
 use aws_sdk_ec2 as ec2;
 use ratatui::crossterm::event;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
@@ -58,7 +60,7 @@ fn clamp_index(idx: usize, len: usize) -> usize {
     if len == 0 { 0 } else { idx.min(len - 1) }
 }
 
-async fn handle_events(state: &mut AppState) -> std::io::Result<bool> {
+async fn handle_events(state: &mut AppState, matches: &clap::ArgMatches) -> std::io::Result<bool> {
     match event::read()? {
         Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
             KeyCode::Char('q') => return Ok(true),
@@ -78,34 +80,31 @@ async fn handle_events(state: &mut AppState) -> std::io::Result<bool> {
                     Page::Instance => state.idx_instance = clamp_index(state.idx_instance + 1, state.instance_names.len()),
                 }
             }
-            KeyCode::Char('c') => {
+            KeyCode::Char('c') if state.page == Page::Instance => {
                 // connect to EC2 when on the Instances page (Cluster repurposed)
-                if state.page == Page::Instance {
-                    if state.instance_names.is_empty() {
-                        return Ok(false);
-                    }
-                    let idx = state.idx_instance;
-                    let target = &state.instance_ids[idx];
-                    ratatui::restore();
-                    connect_to_ec2_command(&target).await;
-                    return Ok(true);
+                if state.instance_names.is_empty() {
+                    return Ok(false);
                 }
+                let idx = state.idx_instance;
+                let target = &state.instance_ids[idx];
+                ratatui::restore();
+                connect_to_ec2_command(matches, target).await;
+                return Ok(true);
             }
 
-            KeyCode::Char('p') => {
-                if state.page == Page::Instance {
-                    if state.instance_names.is_empty() {
-                        return Ok(false);
-                    }
-                    let idx = state.idx_instance;
-                    let target = &state.instance_ids[idx];
-                    ratatui::restore();
-                    let host = crate::commands::port_forward::select_host(&"What host do you want to use?".to_string());
-                    let remote_port = crate::commands::port_forward::select_port(&"What remote port do you want to use?".to_string());
-                    let local_port = crate::commands::port_forward::select_port(&"What local port do you want to use?".to_string());
-                    crate::commands::port_forward::connect_to_ecs_command(&target, &host, &local_port, &remote_port).await;
-                    return Ok(true);
+            KeyCode::Char('p') if state.page == Page::Instance => {
+                if state.instance_names.is_empty() {
+                    return Ok(false);
                 }
+                let idx = state.idx_instance;
+                let target = &state.instance_ids[idx];
+                ratatui::restore();
+                let (host, endpoint_port) = crate::commands::port_forward::select_endpoint(matches).await;
+                let remote_port = endpoint_port
+                    .unwrap_or_else(|| crate::commands::port_forward::select_port("What remote port do you want to use?"));
+                let local_port = crate::commands::port_forward::select_local_port("What local port do you want to use?");
+                crate::commands::port_forward::connect_to_ecs_command(matches, target, &host, local_port, remote_port).await;
+                return Ok(true);
             }
 
 
@@ -149,7 +148,7 @@ fn draw_ecs_connect(frame: &mut Frame, state: &AppState) {
     frame.render_stateful_widget(list, left_area, &mut list_state);
 
     // right: details / selection summary as Vec<Line>
-    let mut details = vec![
+    let details = vec![
         Line::from(Span::raw(format!("Page: {}", state.page.title()))),
         Line::from(""),
         Line::from(Span::raw(format!("Instance:  {}", state.instance_names.get(state.idx_instance).unwrap_or(&"None".to_string())))),
@@ -190,13 +189,15 @@ fn draw_ecs_connect(frame: &mut Frame, state: &AppState) {
     }
 }
 
-pub async fn run_ec2_connect(terminal: &mut ratatui::DefaultTerminal) -> std::io::Result<()> {
+pub async fn run_ec2_connect(
+    terminal: &mut ratatui::DefaultTerminal,
+    client: &ec2::Client,
+    matches: &clap::ArgMatches,
+) -> std::io::Result<()> {
     // initial state - load EC2 instances into the first page
     let mut state = AppState::default();
 
-    let config = aws_config::load_from_env().await;
-    let client = ec2::Client::new(&config);
-    let instances = list_ec2_instances(&client).await;
+    let instances = list_ec2_instances(client).await;
     state.instance_names = instances.iter().map(|i| i.name.clone()).collect();
     state.instance_ids = instances.iter().map(|i| i.instance_id.clone()).collect();
 
@@ -204,7 +205,7 @@ pub async fn run_ec2_connect(terminal: &mut ratatui::DefaultTerminal) -> std::io
     loop {
         // pass the state reference into the draw closure
         terminal.draw(|frame| draw_ecs_connect(frame, &state))?;
-        if handle_events(&mut state).await? {
+        if handle_events(&mut state, matches).await? {
             break Ok(());
         }
 
@@ -212,24 +213,33 @@ pub async fn run_ec2_connect(terminal: &mut ratatui::DefaultTerminal) -> std::io
     }
 }
 
-async fn connect_to_ec2_command(target: &str) {
-    ctrlc::set_handler(move || {}).expect("Error setting Ctrl-C handler");
-
-    let command = format!("aws ssm start-session --target {}", target);
-
-    println!("{}", command);
-
-    let output = std::process::Command::new("/bin/sh")
-        .arg("-c")
-        .arg(command)
-        .spawn()
-        .expect("failed to execute process");
-    let _ = output.wait_with_output();
+pub(crate) async fn connect_to_ec2_command(matches: &clap::ArgMatches, target: &str) {
+    if !use_aws_cli(matches) {
+        let config = load_config(matches).await;
+        let session = ssm_session::start_ssm_session(&config, target, None, &[]).await;
+        ssm_session::exit_with_shell(&config, session).await;
+    }
+    let args: Vec<String> = vec!["ssm".into(), "start-session".into(), "--target".into(), target.into()];
+    run_aws_cli_with(matches, args);
 }
 
+pub async fn ec2_connect(matches: &clap::ArgMatches) {
+    let config = load_config(matches).await;
+    let client = ec2::Client::new(&config);
+
+    if let Some(instance) = matches.get_one::<String>("instance") {
+        match find_ec2_instance(&client, instance).await {
+            Some(target) => connect_to_ec2_command(matches, &target).await,
+            None => {
+                println!("No running instance found for {}", instance);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-pub async fn ec2_connect() {
+    require_interactive("instance");
     let mut terminal = ratatui::init();
-    run_ec2_connect(&mut terminal).await.expect("Can't connect to ec2");
+    run_ec2_connect(&mut terminal, &client, matches).await.expect("Can't connect to ec2");
     ratatui::restore();
 }
\ No newline at end of file
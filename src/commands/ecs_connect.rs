@@ -1,4 +1,5 @@
-use crate::commands::aws_utils::{ecs_execute_command, get_clusters, list_cluster_services, list_service_tasks, list_task_container};
+use crate::commands::aws_utils::{ecs_execute_command, get_clusters, load_config, list_cluster_services, list_service_tasks, list_task_container};
+use crate::commands::cli_utils::require_interactive;
 use ratatui::crossterm::event;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
 use ratatui::{
@@ -66,6 +67,7 @@ struct AppState {
     idx_service: usize,
     idx_task: usize,
     idx_container: usize,
+    command: String,
 }
 
 impl Default for AppState {
@@ -81,6 +83,7 @@ impl Default for AppState {
             idx_service: 0,
             idx_task: 0,
             idx_container: 0,
+            command: "/bin/sh".to_string(),
         }
     }
 }
@@ -113,7 +116,7 @@ fn reset_following(state: &mut AppState, page: Page) {
     }
 }
 
-async fn handle_events(state: &mut AppState) -> std::io::Result<bool> {
+async fn handle_events(state: &mut AppState, matches: &clap::ArgMatches) -> std::io::Result<bool> {
     match event::read()? {
         Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
             KeyCode::Char('q') => return Ok(true),
@@ -141,36 +144,33 @@ async fn handle_events(state: &mut AppState) -> std::io::Result<bool> {
                 }
                 reset_following(state, state.page);
             }
-            KeyCode::Char('c') => {
-                if state.page == Page::Container {
-                    let cluster = &state.clusters[state.idx_cluster];
-                    let task = &state.tasks[state.idx_task];
-                    let container = &state.containers[state.idx_container];
-                    if cluster.is_empty() || task.is_empty() || container.is_empty() {
-                        return Ok(false);
-                    }
-                    ratatui::restore();
-                    ecs_execute_command(cluster, task, container, "/bin/sh").await;
-                    return Ok(true);
+            KeyCode::Char('c') if state.page == Page::Container => {
+                let cluster = &state.clusters[state.idx_cluster];
+                let task = &state.tasks[state.idx_task];
+                let container = &state.containers[state.idx_container];
+                if cluster.is_empty() || task.is_empty() || container.is_empty() {
+                    return Ok(false);
                 }
+                ratatui::restore();
+                ecs_execute_command(matches, cluster, task, container, &state.command).await;
+                return Ok(true);
             }
 
-            KeyCode::Char('p') => {
-                if state.page == Page::Container {
-                    let cluster = &state.clusters[state.idx_cluster];
-                    let task = &state.tasks[state.idx_task];
-                    let runtime_id = &state.runtime_ids[state.idx_container];
-                    if cluster.is_empty() || task.is_empty() || runtime_id.is_empty() {
-                        return Ok(false);
-                    }
-                    ratatui::restore();
-                    let host = crate::commands::port_forward::select_host(&"What host do you want to use?".to_string());
-                    let remote_port = crate::commands::port_forward::select_port(&"What remote port do you want to use?".to_string());
-                    let local_port = crate::commands::port_forward::select_port(&"What local port do you want to use?".to_string());
-                    let target = format!("ecs:{}_{}_{}", cluster, task, runtime_id);
-                    crate::commands::port_forward::connect_to_ecs_command(&target, &host, &local_port, &remote_port).await;
-                    return Ok(true);
+            KeyCode::Char('p') if state.page == Page::Container => {
+                let cluster = &state.clusters[state.idx_cluster];
+                let task = &state.tasks[state.idx_task];
+                let runtime_id = &state.runtime_ids[state.idx_container];
+                if cluster.is_empty() || task.is_empty() || runtime_id.is_empty() {
+                    return Ok(false);
                 }
+                ratatui::restore();
+                let (host, endpoint_port) = crate::commands::port_forward::select_endpoint(matches).await;
+                let remote_port = endpoint_port
+                    .unwrap_or_else(|| crate::commands::port_forward::select_port("What remote port do you want to use?"));
+                let local_port = crate::commands::port_forward::select_local_port("What local port do you want to use?");
+                let target = format!("ecs:{}_{}_{}", cluster, task, runtime_id);
+                crate::commands::port_forward::connect_to_ecs_command(matches, &target, &host, local_port, remote_port).await;
+                return Ok(true);
             }
 
 
@@ -265,43 +265,126 @@ fn draw_ecs_connect(frame: &mut Frame, state: &AppState) {
     }
 }
 
-pub async fn run_ecs_connect(terminal: &mut ratatui::DefaultTerminal) -> std::io::Result<()> {
+async fn load_page(client: &aws_sdk_ecs::Client, clusters: &[AwsResource], state: &mut AppState) {
+    if state.page == Page::Services && state.services.is_empty() {
+        let cluster_arn = &clusters[state.idx_cluster].arn;
+        let services = list_cluster_services(client, cluster_arn).await;
+        state.services = services.iter().map(|s| s.name.clone()).collect();
+    } else if state.page == Page::Tasks && state.tasks.is_empty() {
+        let cluster_arn = &clusters[state.idx_cluster].arn;
+        let service_name = &state.services[state.idx_service];
+        let tasks = list_service_tasks(client, cluster_arn, service_name).await;
+        state.tasks = tasks.iter().map(|t| t.name.clone()).collect();
+    } else if state.page == Page::Container && state.containers.is_empty() {
+        let cluster_arn = &clusters[state.idx_cluster].arn;
+        let task_id = &state.tasks[state.idx_task];
+        let containers = list_task_container(client, cluster_arn, task_id).await;
+        state.containers = containers.iter().map(|c| c.name.clone()).collect();
+        state.runtime_ids = containers.iter().map(|c| c.runtime_id.clone()).collect();
+    }
+}
+
+// Move the TUI to the first page whose value wasn't given on the command line
+async fn preselect(
+    client: &aws_sdk_ecs::Client,
+    clusters: &[AwsResource],
+    state: &mut AppState,
+    matches: &clap::ArgMatches,
+) {
+    let Some(cluster) = matches.get_one::<String>("cluster") else { return };
+    let Some(idx) = state.clusters.iter().position(|c| c == cluster) else { return };
+    state.idx_cluster = idx;
+    state.page = Page::Services;
+    load_page(client, clusters, state).await;
+
+    let Some(service) = matches.get_one::<String>("service") else { return };
+    let Some(idx) = state.services.iter().position(|s| s == service) else { return };
+    state.idx_service = idx;
+    state.page = Page::Tasks;
+    load_page(client, clusters, state).await;
+
+    let Some(task) = matches.get_one::<String>("task") else { return };
+    let Some(idx) = state.tasks.iter().position(|t| t == task) else { return };
+    state.idx_task = idx;
+    state.page = Page::Container;
+    load_page(client, clusters, state).await;
+}
+
+pub async fn run_ecs_connect(
+    terminal: &mut ratatui::DefaultTerminal,
+    client: &aws_sdk_ecs::Client,
+    matches: &clap::ArgMatches,
+) -> std::io::Result<()> {
     // initial state - optionally load clusters here asynchronously
-    let mut state = AppState::default();
+    let mut state = AppState {
+        command: matches.get_one::<String>("command").unwrap().clone(),
+        ..AppState::default()
+    };
 
-    let config = aws_config::load_from_env().await;
-    let client = aws_sdk_ecs::Client::new(&config);
-    let clusters = get_clusters(&client).await;
+    let clusters = get_clusters(client).await;
     state.clusters = clusters.iter().map(|c| c.name.clone()).collect();
+    preselect(client, &clusters, &mut state, matches).await;
 
     loop {
         // pass the state reference into the draw closure
         terminal.draw(|frame| draw_ecs_connect(frame, &state))?;
-        if handle_events(&mut state).await? {
+        if handle_events(&mut state, matches).await? {
             break Ok(());
         }
-        if state.page == Page::Services && state.services.is_empty() {
-            let cluster_arn = &clusters[state.idx_cluster].arn;
-            let services = list_cluster_services(&client, cluster_arn).await;
-            state.services = services.iter().map(|s| s.name.clone()).collect();
-        } else if state.page == Page::Tasks && state.tasks.is_empty() {
-            let cluster_arn = &clusters[state.idx_cluster].arn;
-            let service_name = &state.services[state.idx_service];
-            let tasks = list_service_tasks(&client, cluster_arn, service_name).await;
-            state.tasks = tasks.iter().map(|t| t.name.clone()).collect();
-        } else if state.page == Page::Container && state.containers.is_empty() {
-            let cluster_arn = &clusters[state.idx_cluster].arn;
-            let task_id = &state.tasks[state.idx_task];
-            let containers = list_task_container(&client, cluster_arn, task_id).await;
-            state.containers = containers.iter().map(|c| c.name.clone()).collect();
-            state.runtime_ids = containers.iter().map(|c| c.runtime_id.clone()).collect();
-        }
+        load_page(client, &clusters, &mut state).await;
     }
 }
 
+// Resolve the container to connect to from the command line, or return the first missing flag
+async fn resolve_ecs_target(
+    client: &aws_sdk_ecs::Client,
+    matches: &clap::ArgMatches,
+) -> Result<(String, String, String), &'static str> {
+    let cluster = matches.get_one::<String>("cluster").ok_or("cluster")?;
 
-pub async fn ecs_connect() {
-    let mut terminal = ratatui::init();
-    run_ecs_connect(&mut terminal).await.expect("TODO: Ecs connect failed");
-    ratatui::restore();
-}
\ No newline at end of file
+    let task = match (matches.get_one::<String>("task"), matches.get_one::<String>("service")) {
+        (Some(task), _) => task.clone(),
+        (None, Some(service)) => {
+            let tasks = list_service_tasks(client, cluster, service).await;
+            match tasks.first() {
+                Some(task) => task.name.clone(),
+                None => {
+                    println!("No running task found for service {}", service);
+                    std::process::exit(1);
+                }
+            }
+        }
+        (None, None) => return Err("task"),
+    };
+
+    let container = match matches.get_one::<String>("container") {
+        Some(container) => container.clone(),
+        None => {
+            let containers = list_task_container(client, cluster, &task).await;
+            if containers.len() != 1 {
+                return Err("container");
+            }
+            containers[0].name.clone()
+        }
+    };
+
+    Ok((cluster.clone(), task, container))
+}
+
+pub async fn ecs_connect(matches: &clap::ArgMatches) {
+    let config = load_config(matches).await;
+    let client = aws_sdk_ecs::Client::new(&config);
+
+    match resolve_ecs_target(&client, matches).await {
+        Ok((cluster, task, container)) => {
+            let command = matches.get_one::<String>("command").unwrap();
+            ecs_execute_command(matches, &cluster, &task, &container, command).await;
+        }
+        Err(missing) => {
+            require_interactive(missing);
+            let mut terminal = ratatui::init();
+            run_ecs_connect(&mut terminal, &client, matches).await.expect("TODO: Ecs connect failed");
+            ratatui::restore();
+        }
+    }
+}
@@ -0,0 +1,680 @@
+use crate::commands::aws_utils::{aws_cli_args, check_session_manager, load_config, resolve_target, use_aws_cli};
+use crate::commands::cli_utils::{format_bytes, is_interactive};
+use crate::commands::forward_daemon;
+use crate::commands::port_forward::{parse_port, port_forward_args};
+use crate::commands::profile::profile_forward_spec;
+use crate::commands::ssm_session::{
+    forward_port, into_async_listener, start_port_session, terminate_session, DataChannel, Traffic,
+};
+use aws_config::SdkConfig;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    Frame,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// Delays between the reconnections of --keep-alive, doubled after each failure
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+const STABLE_SESSION: Duration = Duration::from_secs(30);
+// Printed by session-manager-plugin once the local port listens
+const PLUGIN_READY: &str = "Waiting for connections";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ForwardSpec {
+    // Any target of aws_utils::resolve_target
+    pub(crate) target: String,
+    pub(crate) host: String,
+    pub(crate) remote_port: u16,
+    pub(crate) local_port: u16,
+}
+
+impl ForwardSpec {
+    // TARGET:HOST:REMOTE_PORT:LOCAL_PORT, the target may contain a colon itself (ecs:...)
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid forward {}, expected TARGET:HOST:REMOTE_PORT:LOCAL_PORT", spec);
+        let mut parts = spec.rsplitn(4, ':');
+        let local_port = parts.next().and_then(|p| parse_port(p).ok()).ok_or_else(invalid)?;
+        let remote_port = parts.next().and_then(|p| parse_port(p).ok()).ok_or_else(invalid)?;
+        let host = parts.next().filter(|h| !h.is_empty()).ok_or_else(invalid)?;
+        let target = parts.next().filter(|t| !t.is_empty()).ok_or_else(invalid)?;
+        Ok(Self {
+            target: target.to_string(),
+            host: host.to_string(),
+            remote_port,
+            local_port,
+        })
+    }
+
+    pub(crate) fn remote(&self) -> String {
+        format!("{}:{}", self.host, self.remote_port)
+    }
+}
+
+// Forwards file: a list of [[forward]] tables with the ForwardSpec fields
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ForwardFile {
+    forward: Vec<ForwardSpec>,
+}
+
+fn read_forward_file(path: &str) -> Result<Vec<ForwardSpec>, String> {
+    let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let file: ForwardFile = toml::from_str(&content).map_err(|error| error.to_string())?;
+    Ok(file.forward)
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) enum Status {
+    Starting,
+    Ready,
+    Stopped,
+    Failed(String),
+    Reconnecting(Duration, String),
+}
+
+impl Status {
+    pub(crate) fn label(&self) -> String {
+        match self {
+            Status::Starting => "starting".to_string(),
+            Status::Ready => "ready".to_string(),
+            Status::Stopped => "stopped".to_string(),
+            Status::Failed(error) => format!("failed: {}", error),
+            Status::Reconnecting(delay, error) => format!("reconnecting in {}s: {}", delay.as_secs(), error),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Status::Starting => Color::Yellow,
+            Status::Ready => Color::Green,
+            Status::Stopped => Color::DarkGray,
+            Status::Failed(_) => Color::Red,
+            Status::Reconnecting(_, _) => Color::Magenta,
+        }
+    }
+}
+
+struct ForwardState {
+    status: Status,
+    // Since when the current session is ready
+    since: Option<Instant>,
+}
+
+type SharedState = Arc<Mutex<ForwardState>>;
+
+fn set_status(state: &SharedState, status: Status) {
+    let mut state = state.lock().unwrap();
+    state.since = (status == Status::Ready).then(Instant::now);
+    state.status = status;
+}
+
+// How the sessions of a forward run, the forwards of the daemon come from different shells
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub(crate) config: SdkConfig,
+    // --profile/--region for the aws CLI, None to use the built-in client
+    pub(crate) aws_cli: Option<Vec<String>>,
+    pub(crate) keep_alive: bool,
+}
+
+impl Settings {
+    pub(crate) async fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            config: load_config(matches).await,
+            aws_cli: use_aws_cli(matches).then(|| aws_cli_args(matches, Vec::new())),
+            keep_alive: matches.get_flag("keep-alive"),
+        }
+    }
+}
+
+pub(crate) struct Forward {
+    pub(crate) id: u32,
+    pub(crate) spec: ForwardSpec,
+    settings: Settings,
+    // Kept across restarts so that the local port stays ours. None with --aws-cli, the
+    // session-manager-plugin listens itself
+    listener: Option<Arc<TcpListener>>,
+    state: SharedState,
+    traffic: Arc<Traffic>,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Forward {
+    fn new(id: u32, spec: ForwardSpec, settings: Settings) -> Self {
+        Self {
+            id,
+            spec,
+            settings,
+            listener: None,
+            state: Arc::new(Mutex::new(ForwardState { status: Status::Stopped, since: None })),
+            traffic: Arc::new(Traffic::default()),
+            stop: None,
+            task: None,
+        }
+    }
+
+    pub(crate) fn status(&self) -> Status {
+        self.state.lock().unwrap().status.clone()
+    }
+
+    pub(crate) fn uptime(&self) -> String {
+        let Some(since) = self.state.lock().unwrap().since else {
+            return "-".to_string();
+        };
+        let seconds = since.elapsed().as_secs();
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+
+    // The aws CLI doesn't tell
+    pub(crate) fn bytes(&self) -> String {
+        if self.settings.aws_cli.is_some() {
+            return "-".to_string();
+        }
+        format!(
+            "↑ {} ↓ {}",
+            format_bytes(self.traffic.sent.load(Ordering::Relaxed)),
+            format_bytes(self.traffic.received.load(Ordering::Relaxed))
+        )
+    }
+}
+
+// Starts, stops and restarts the forwards, each one runs in its own task
+#[derive(Default)]
+pub(crate) struct Supervisor {
+    next_id: u32,
+    pub(crate) forwards: Vec<Forward>,
+}
+
+impl Supervisor {
+    // Starts the forwards, returns their IDs
+    pub(crate) fn add(&mut self, specs: Vec<ForwardSpec>, settings: &Settings) -> Vec<u32> {
+        let mut ids = Vec::new();
+        for spec in specs {
+            self.next_id += 1;
+            ids.push(self.next_id);
+            self.forwards.push(Forward::new(self.next_id, spec, settings.clone()));
+            self.start(self.forwards.len() - 1);
+        }
+        ids
+    }
+
+    // Forward on a port the caller already listens on
+    fn add_listening(&mut self, spec: ForwardSpec, settings: &Settings, listener: TcpListener) -> u32 {
+        self.next_id += 1;
+        let mut forward = Forward::new(self.next_id, spec, settings.clone());
+        forward.listener = Some(Arc::new(listener));
+        self.forwards.push(forward);
+        self.start(self.forwards.len() - 1);
+        self.next_id
+    }
+
+    fn start(&mut self, idx: usize) {
+        let forward = &mut self.forwards[idx];
+        if forward.task.is_some() {
+            return;
+        }
+        if forward.settings.aws_cli.is_none() && forward.listener.is_none() {
+            match bind(forward.spec.local_port) {
+                Ok(listener) => forward.listener = Some(Arc::new(listener)),
+                Err(error) => {
+                    set_status(&forward.state, Status::Failed(error));
+                    return;
+                }
+            }
+        }
+        set_status(&forward.state, Status::Starting);
+        let (stop, stop_rx) = oneshot::channel();
+        forward.stop = Some(stop);
+        let runner = Runner {
+            config: forward.settings.config.clone(),
+            spec: forward.spec.clone(),
+            listener: forward.listener.clone(),
+            aws_cli: forward.settings.aws_cli.clone(),
+            state: forward.state.clone(),
+            traffic: forward.traffic.clone(),
+            keep_alive: forward.settings.keep_alive,
+        };
+        forward.task = Some(tokio::spawn(runner.run(stop_rx)));
+    }
+
+    async fn stop(&mut self, idx: usize) {
+        let forward = &mut self.forwards[idx];
+        if let Some(stop) = forward.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(task) = forward.task.take() {
+            let _ = task.await;
+        }
+    }
+
+    async fn restart(&mut self, idx: usize) {
+        self.stop(idx).await;
+        self.start(idx);
+    }
+
+    // Stops the forward and frees its local port
+    pub(crate) async fn remove(&mut self, id: u32) -> bool {
+        let Some(idx) = self.forwards.iter().position(|f| f.id == id) else {
+            return false;
+        };
+        self.stop(idx).await;
+        self.forwards.remove(idx);
+        true
+    }
+
+    pub(crate) async fn stop_all(&mut self) {
+        for idx in 0..self.forwards.len() {
+            self.stop(idx).await;
+        }
+    }
+
+    // A task ends by itself when its session fails, the local port is freed until a restart.
+    // Returns the forwards that ended
+    pub(crate) fn reap(&mut self) -> Vec<&Forward> {
+        let mut ended = Vec::new();
+        for forward in &mut self.forwards {
+            if forward.task.as_ref().is_some_and(|t| t.is_finished()) {
+                forward.task = None;
+                forward.stop = None;
+                forward.listener = None;
+                ended.push(&*forward);
+            }
+        }
+        ended
+    }
+
+    pub(crate) fn running(&self) -> bool {
+        self.forwards.iter().any(|f| f.task.is_some())
+    }
+}
+
+fn bind(port: u16) -> Result<TcpListener, String> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+        .map_err(|error| format!("can't listen on localhost:{}: {}", port, error))?;
+    into_async_listener(listener)
+}
+
+// Session of the built-in client, the error says why it ended
+async fn native_session(
+    config: &SdkConfig,
+    spec: &ForwardSpec,
+    listener: &TcpListener,
+    state: &SharedState,
+    traffic: &Traffic,
+    session_id: &mut Option<String>,
+) -> Result<(), String> {
+    let target = resolve_target(config, &spec.target).await?.ssm_target();
+    let session = start_port_session(config, &target, &spec.host, spec.local_port, spec.remote_port).await?;
+    *session_id = Some(session.session_id.clone());
+    let mut channel = DataChannel::open(&session).await?;
+    let (handshake, _) = channel.wait_ready().await?;
+    set_status(state, Status::Ready);
+    let reason = forward_port(&mut channel, &handshake, listener, traffic).await;
+    Err(reason.unwrap_or_else(|| "the session was closed".to_string()))
+}
+
+// The session lives as long as the aws CLI child (killed when dropped), its last stderr line
+// says why it ended
+async fn aws_cli_session(
+    config: &SdkConfig,
+    spec: &ForwardSpec,
+    aws_cli: &[String],
+    state: &SharedState,
+) -> Result<(), String> {
+    check_session_manager()?;
+    let target = resolve_target(config, &spec.target).await?.ssm_target();
+    let mut args = port_forward_args(&target, &spec.host, spec.local_port, spec.remote_port);
+    args.extend_from_slice(aws_cli);
+    let mut child = tokio::process::Command::new("aws")
+        .args(&args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| format!("can't run the aws CLI: {}", error))?;
+    let mut stderr = child.stderr.take().unwrap();
+    let errors = tokio::spawn(async move {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output).await;
+        output
+    });
+    // The local port only accepts connections once the plugin says so
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let ready_state = state.clone();
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = stdout.next_line().await {
+            if line.contains(PLUGIN_READY) {
+                set_status(&ready_state, Status::Ready);
+            }
+        }
+    });
+
+    let status = child.wait().await;
+    let output = errors.await.unwrap_or_default();
+    let error = output.lines().rev().find(|l| !l.trim().is_empty()).map(|l| l.trim().to_string());
+    match (status, error) {
+        (_, Some(error)) => Err(error),
+        (Ok(status), None) => Err(format!("the aws CLI exited with {}", status)),
+        (Err(error), None) => Err(error.to_string()),
+    }
+}
+
+struct Runner {
+    config: SdkConfig,
+    spec: ForwardSpec,
+    // None with --aws-cli
+    listener: Option<Arc<TcpListener>>,
+    aws_cli: Option<Vec<String>>,
+    state: SharedState,
+    traffic: Arc<Traffic>,
+    keep_alive: bool,
+}
+
+impl Runner {
+    async fn session(&self, session_id: &mut Option<String>) -> Result<(), String> {
+        match (&self.listener, &self.aws_cli) {
+            (Some(listener), _) => {
+                native_session(&self.config, &self.spec, listener, &self.state, &self.traffic, session_id).await
+            }
+            (None, Some(aws_cli)) => aws_cli_session(&self.config, &self.spec, aws_cli, &self.state).await,
+            (None, None) => Err("no local listener".to_string()),
+        }
+    }
+
+    // Run sessions until stopped. Without keep-alive the first drop is final, with it the target
+    // is resolved again (another task of the service, another instance with the tag) and a new
+    // session takes over the same local port
+    async fn run(self, mut stop: oneshot::Receiver<()>) {
+        let mut delay = RECONNECT_DELAY_MIN;
+        loop {
+            let mut session_id = None;
+            let result = tokio::select! {
+                result = self.session(&mut session_id) => Some(result),
+                _ = &mut stop => None,
+            };
+            if let Some(session_id) = session_id {
+                terminate_session(&self.config, &session_id).await;
+            }
+            let error = match result {
+                None => break,
+                Some(Ok(_)) => "the session was closed".to_string(),
+                Some(Err(error)) => error,
+            };
+            if !self.keep_alive {
+                set_status(&self.state, Status::Failed(error));
+                return;
+            }
+            // A session that stayed up for a while starts the backoff over
+            if self.state.lock().unwrap().since.is_some_and(|since| since.elapsed() >= STABLE_SESSION) {
+                delay = RECONNECT_DELAY_MIN;
+            }
+            set_status(&self.state, Status::Reconnecting(delay, error));
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut stop => break,
+            }
+            delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+            set_status(&self.state, Status::Starting);
+        }
+        set_status(&self.state, Status::Stopped);
+    }
+}
+
+fn draw_forwards(frame: &mut Frame, supervisor: &Supervisor, table_state: &mut TableState) {
+    use Constraint::{Fill, Length, Min};
+
+    let vertical = Layout::vertical([Min(0), Length(3)]);
+    let [main_area, status_area] = vertical.areas(frame.area());
+
+    let rows: Vec<Row> = supervisor
+        .forwards
+        .iter()
+        .map(|forward| {
+            let status = forward.status();
+            Row::new(vec![
+                forward.spec.target.clone(),
+                forward.spec.local_port.to_string(),
+                forward.spec.remote(),
+                status.label(),
+                forward.uptime(),
+                forward.bytes(),
+            ])
+            .style(Style::default().fg(status.color()))
+        })
+        .collect();
+    let table = Table::new(rows, [Fill(3), Length(10), Fill(3), Fill(3), Length(10), Length(24)])
+        .header(
+            Row::new(vec!["Target", "Local port", "Remote", "Status", "Uptime", "Bytes"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title("Port forwards"))
+        .row_highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED));
+    frame.render_stateful_widget(table, main_area, table_state);
+
+    let help = Paragraph::new("Use ↑/↓ to move selection, s to stop, r to restart, q to stop all and quit.")
+        .block(Block::bordered());
+    frame.render_widget(help, status_area);
+}
+
+async fn run_forwards(terminal: &mut ratatui::DefaultTerminal, supervisor: &mut Supervisor) -> std::io::Result<()> {
+    let mut table_state = TableState::default().with_selected(Some(0));
+
+    loop {
+        supervisor.reap();
+        terminal.draw(|frame| draw_forwards(frame, supervisor, &mut table_state))?;
+        // Redraw every second for the uptime and bytes even without a key press
+        if !event::poll(Duration::from_secs(1))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let idx = table_state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') => return Ok(()),
+            KeyCode::Up => table_state.select(Some(idx.saturating_sub(1))),
+            KeyCode::Down => table_state.select(Some((idx + 1).min(supervisor.forwards.len() - 1))),
+            KeyCode::Char('s') => supervisor.stop(idx).await,
+            KeyCode::Char('r') => supervisor.restart(idx).await,
+            _ => {}
+        }
+    }
+}
+
+// Without a terminal, log the status changes until Ctrl-C
+async fn log_forwards(supervisor: &mut Supervisor) {
+    let mut statuses: Vec<Option<Status>> = vec![None; supervisor.forwards.len()];
+    let mut tick = tokio::time::interval(Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {}
+            _ = tokio::signal::ctrl_c() => return,
+        }
+        supervisor.reap();
+        let running = supervisor.running();
+        for (forward, last) in supervisor.forwards.iter().zip(statuses.iter_mut()) {
+            let status = forward.status();
+            if last.as_ref() != Some(&status) {
+                println!(
+                    "{} localhost:{} -> {}: {}",
+                    forward.spec.target,
+                    forward.spec.local_port,
+                    forward.spec.remote(),
+                    status.label()
+                );
+                *last = Some(status);
+            }
+        }
+        if !running {
+            return;
+        }
+    }
+}
+
+// Forwards of the profiles, --file and --forward
+pub(crate) fn forward_specs(matches: &clap::ArgMatches) -> Vec<ForwardSpec> {
+    let mut specs: Vec<ForwardSpec> = matches
+        .get_many::<String>("name")
+        .unwrap_or_default()
+        .map(|name| profile_forward_spec(name))
+        .collect();
+    if let Some(path) = matches.get_one::<String>("file") {
+        match read_forward_file(path) {
+            Ok(forwards) => specs.extend(forwards),
+            Err(error) => {
+                println!("Invalid forwards file {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    for spec in matches.get_many::<String>("forward").unwrap_or_default() {
+        match ForwardSpec::parse(spec) {
+            Ok(spec) => specs.push(spec),
+            Err(error) => {
+                println!("{}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if specs.is_empty() {
+        println!("No port forward given, use a profile, --forward or --file");
+        std::process::exit(1);
+    }
+    specs
+}
+
+pub async fn forward(matches: &clap::ArgMatches) {
+    match matches.subcommand() {
+        Some(("start", sub_matches)) if sub_matches.get_flag("detach") => forward_daemon::start(sub_matches).await,
+        Some(("start", sub_matches)) => run_forwards_in_foreground(sub_matches).await,
+        Some(("list", _)) => forward_daemon::list().await,
+        Some(("stop", sub_matches)) => forward_daemon::stop(*sub_matches.get_one::<u32>("id").unwrap()).await,
+        Some(("daemon", _)) => forward_daemon::run_daemon().await,
+        _ => run_forwards_in_foreground(matches).await,
+    }
+}
+
+async fn run_forwards_in_foreground(matches: &clap::ArgMatches) {
+    let specs = forward_specs(matches);
+    let mut supervisor = Supervisor::default();
+    supervisor.add(specs, &Settings::from_matches(matches).await);
+    if is_interactive() {
+        let mut terminal = ratatui::init();
+        let result = run_forwards(&mut terminal, &mut supervisor).await;
+        ratatui::restore();
+        result.expect("Can't display the port forwards");
+    } else {
+        log_forwards(&mut supervisor).await;
+    }
+    supervisor.stop_all().await;
+}
+
+// Single forward of port-forward/connect --keep-alive, reconnections are logged until Ctrl-C
+// `listener` holds the local port until the forward takes it over, the aws CLI binds it itself
+pub(crate) async fn keep_alive(matches: &clap::ArgMatches, spec: ForwardSpec, listener: Option<std::net::TcpListener>) {
+    let settings = Settings::from_matches(matches).await;
+    let mut supervisor = Supervisor::default();
+    match listener.filter(|_| settings.aws_cli.is_none()).map(into_async_listener) {
+        Some(Ok(listener)) => {
+            supervisor.add_listening(spec, &settings, listener);
+        }
+        Some(Err(error)) => {
+            println!("Can't listen on localhost:{}: {}", spec.local_port, error);
+            std::process::exit(1);
+        }
+        None => {
+            supervisor.add(vec![spec], &settings);
+        }
+    }
+    log_forwards(&mut supervisor).await;
+    supervisor.stop_all().await;
+    if let Status::Failed(_) = supervisor.forwards[0].status() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> (String, String, u16, u16) {
+        let spec = ForwardSpec::parse(spec).unwrap();
+        (spec.target, spec.host, spec.remote_port, spec.local_port)
+    }
+
+    #[test]
+    fn parse_forward() {
+        assert_eq!(
+            parse("i-0123456789abcdef0:localhost:5432:15432"),
+            ("i-0123456789abcdef0".to_string(), "localhost".to_string(), 5432, 15432)
+        );
+        assert_eq!(
+            parse("tag:Name=bastion:db.internal:3306:3306"),
+            ("tag:Name=bastion".to_string(), "db.internal".to_string(), 3306, 3306)
+        );
+    }
+
+    #[test]
+    fn parse_forward_keeps_the_colons_of_the_target() {
+        assert_eq!(
+            parse("ecs-service:prod/api/app:redis.internal:6379:16379"),
+            ("ecs-service:prod/api/app".to_string(), "redis.internal".to_string(), 6379, 16379)
+        );
+        assert_eq!(ForwardSpec::parse("ecs-task:prod/abc:localhost:80:8080").unwrap().remote(), "localhost:80");
+    }
+
+    #[test]
+    fn parse_forward_rejects_invalid_specs() {
+        for spec in [
+            "",
+            "i-123",
+            "i-123:5432:15432",
+            ":localhost:5432:15432",
+            "i-123::5432:15432",
+            "i-123:localhost:5432:",
+            "i-123:localhost:0:15432",
+            "i-123:localhost:5432:65536",
+            "i-123:localhost:db:15432",
+        ] {
+            let error = ForwardSpec::parse(spec).err().unwrap_or_else(|| panic!("{} was accepted", spec));
+            assert!(error.contains("TARGET:HOST:REMOTE_PORT:LOCAL_PORT"));
+        }
+    }
+
+    #[test]
+    fn read_forwards_file() {
+        let path = std::env::temp_dir().join(format!("devops-cli-forwards-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[[forward]]\ntarget = \"i-123\"\nhost = \"localhost\"\nremote_port = 5432\nlocal_port = 15432\n\n\
+             [[forward]]\ntarget = \"ecs-service:prod/api\"\nhost = \"redis\"\nremote_port = 6379\nlocal_port = 16379\n",
+        )
+        .unwrap();
+        let forwards = read_forward_file(path.to_str().unwrap());
+        std::fs::write(&path, "[[forward]]\ntarget = \"i-123\"\n").unwrap();
+        let invalid = read_forward_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let forwards = forwards.unwrap();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!((forwards[1].target.as_str(), forwards[1].remote().as_str()), ("ecs-service:prod/api", "redis:6379"));
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,559 @@
+// Background forwards: `forward start --detach` hands the forwards to a daemon owning the
+// sessions, `forward list`/`forward stop` talk to it over a unix socket (one JSON line each way)
+use crate::commands::aws_utils::{aws_cli_args, load_profile_config, use_aws_cli};
+use crate::commands::forward::{forward_specs, ForwardSpec, Settings, Supervisor};
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DIR_MODE: u32 = 0o700;
+const SOCKET_MODE: u32 = 0o600;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum Request {
+    Start {
+        forwards: Vec<ForwardSpec>,
+        profile: Option<String>,
+        region: Option<String>,
+        aws_cli: Option<Vec<String>>,
+        keep_alive: bool,
+        env: BTreeMap<String, String>,
+    },
+    List,
+    Stop {
+        id: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct ForwardInfo {
+    id: u32,
+    target: String,
+    local_port: u16,
+    remote: String,
+    status: String,
+    uptime: String,
+    bytes: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+enum Response {
+    Started { ids: Vec<u32> },
+    Forwards { forwards: Vec<ForwardInfo> },
+    Stopped,
+    Error { message: String },
+}
+
+fn uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+// $XDG_RUNTIME_DIR/devops-cli, or a directory of the user in /tmp
+fn runtime_dir() -> PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("devops-cli"),
+        _ => std::env::temp_dir().join(format!("devops-cli-{}", uid())),
+    }
+}
+
+// Anyone able to replace the directory or the socket could pose as the daemon, both must be ours
+// and closed to other users. lstat so that a symlink planted in /tmp isn't followed
+fn check_private(path: &Path, mode: u32) -> Result<(), String> {
+    let metadata = std::fs::symlink_metadata(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let file_type = metadata.file_type();
+    let expected_type = if mode == DIR_MODE { file_type.is_dir() } else { file_type.is_socket() };
+    if !expected_type || metadata.uid() != uid() || metadata.mode() & 0o777 != mode {
+        return Err(format!(
+            "{} isn't a {} owned by you with mode {:o}, refusing to use it",
+            path.display(),
+            if mode == DIR_MODE { "directory" } else { "socket" },
+            mode
+        ));
+    }
+    Ok(())
+}
+
+fn create_runtime_dir() -> Result<(), String> {
+    let dir = runtime_dir();
+    match std::fs::DirBuilder::new().mode(DIR_MODE).create(&dir) {
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(error) => return Err(format!("can't create {}: {}", dir.display(), error)),
+    }
+    check_private(&dir, DIR_MODE)
+}
+
+// None when no daemon is running, an error when the socket can't be trusted
+async fn connect() -> Result<Option<UnixStream>, String> {
+    let (dir, path) = (runtime_dir(), socket_path());
+    if std::fs::symlink_metadata(&dir).is_err() || std::fs::symlink_metadata(&path).is_err() {
+        return Ok(None);
+    }
+    check_private(&dir, DIR_MODE)?;
+    check_private(&path, SOCKET_MODE)?;
+    Ok(UnixStream::connect(path).await.ok())
+}
+
+// The daemon loads the AWS config from its own environment, a client with another one (profile,
+// credentials, region...) would get forwards it didn't ask for
+fn aws_env() -> BTreeMap<String, String> {
+    std::env::vars().filter(|(name, _)| name.starts_with("AWS_")).collect()
+}
+
+fn env_differences<'a>(ours: &'a BTreeMap<String, String>, theirs: &'a BTreeMap<String, String>) -> Vec<&'a str> {
+    let names: std::collections::BTreeSet<&str> = ours.keys().chain(theirs.keys()).map(|name| name.as_str()).collect();
+    names.into_iter().filter(|name| ours.get(*name) != theirs.get(*name)).collect()
+}
+
+fn socket_path() -> PathBuf {
+    runtime_dir().join("forward.sock")
+}
+
+// Held for the life of the daemon: two daemons started at the same time would otherwise both
+// pass the connection check, and the second one would replace the socket of the first
+fn lock_daemon() -> Result<std::fs::File, String> {
+    let path = runtime_dir().join("forward.lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(SOCKET_MODE)
+        .open(&path)
+        .map_err(|error| format!("can't open {}: {}", path.display(), error))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(format!("A forward daemon is already running on {}", socket_path().display()));
+    }
+    Ok(file)
+}
+
+fn log_path() -> PathBuf {
+    runtime_dir().join("forward.log")
+}
+
+async fn send(stream: UnixStream, request: &Request) -> Result<Response, String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(request).unwrap();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await.map_err(|error| error.to_string())?;
+
+    let mut response = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, BufReader::new(reader).read_line(&mut response))
+        .await
+        .map_err(|_| "the forward daemon didn't answer".to_string())?
+        .map_err(|error| error.to_string())?;
+    serde_json::from_str(&response).map_err(|error| format!("invalid answer of the forward daemon: {}", error))
+}
+
+// Connection to the daemon, started in its own process group so that closing the terminal doesn't stop it
+async fn ensure_daemon() -> Result<UnixStream, String> {
+    create_runtime_dir()?;
+    if let Some(stream) = connect().await? {
+        return Ok(stream);
+    }
+    // Appended to, a daemon losing the race to another one mustn't truncate its log
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(SOCKET_MODE)
+        .open(log_path())
+        .map_err(|error| error.to_string())?;
+    let exe = std::env::current_exe().map_err(|error| error.to_string())?;
+    std::process::Command::new(exe)
+        .args(["forward", "daemon"])
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone().map_err(|error| error.to_string())?)
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .map_err(|error| format!("can't start the forward daemon: {}", error))?;
+
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let Some(stream) = connect().await? {
+            return Ok(stream);
+        }
+    }
+    Err(format!("the forward daemon didn't start, see {}", log_path().display()))
+}
+
+pub(crate) async fn start(matches: &clap::ArgMatches) {
+    let forwards = forward_specs(matches);
+    let stream = match ensure_daemon().await {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let request = Request::Start {
+        forwards: forwards.clone(),
+        profile: matches.get_one::<String>("profile").cloned(),
+        region: matches.get_one::<String>("region").cloned(),
+        aws_cli: use_aws_cli(matches).then(|| aws_cli_args(matches, Vec::new())),
+        keep_alive: matches.get_flag("keep-alive"),
+        env: aws_env(),
+    };
+    match send(stream, &request).await {
+        Ok(Response::Started { ids }) => {
+            for (id, forward) in ids.iter().zip(&forwards) {
+                println!(
+                    "Forward {} started: {} localhost:{} -> {}",
+                    id.to_string().bold(),
+                    forward.target,
+                    forward.local_port,
+                    forward.remote()
+                );
+            }
+            println!("Use devops-cli forward list to check them");
+        }
+        Ok(Response::Error { message }) => {
+            println!("Failed to start the forwards: {}", message);
+            std::process::exit(1);
+        }
+        Ok(_) => {
+            println!("Unexpected answer of the forward daemon");
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to start the forwards: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Connection to the running daemon, None when there is none
+async fn connect_or_exit() -> Option<UnixStream> {
+    match connect().await {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub(crate) async fn list() {
+    let Some(stream) = connect_or_exit().await else {
+        println!("No port forward running in the background");
+        return;
+    };
+    let forwards = match send(stream, &Request::List).await {
+        Ok(Response::Forwards { forwards }) => forwards,
+        Ok(_) => {
+            println!("Unexpected answer of the forward daemon");
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to list the forwards: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["ID", "Target", "Local port", "Remote", "Status", "Uptime", "Bytes"]);
+    for forward in forwards {
+        table.add_row(vec![
+            forward.id.to_string(),
+            forward.target,
+            forward.local_port.to_string(),
+            forward.remote,
+            forward.status,
+            forward.uptime,
+            forward.bytes,
+        ]);
+    }
+    println!("{}", table);
+}
+
+pub(crate) async fn stop(id: u32) {
+    let Some(stream) = connect_or_exit().await else {
+        println!("No port forward running in the background");
+        std::process::exit(1);
+    };
+    match send(stream, &Request::Stop { id }).await {
+        Ok(Response::Stopped) => println!("Forward {} stopped {}", id, "successfully".green().bold()),
+        Ok(Response::Error { message }) => {
+            println!("Failed to stop forward {}: {}", id, message);
+            std::process::exit(1);
+        }
+        Ok(_) => {
+            println!("Unexpected answer of the forward daemon");
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to stop forward {}: {}", id, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn handle(request: Request, supervisor: &mut Supervisor, env: &BTreeMap<String, String>) -> Response {
+    match request {
+        Request::Start { forwards, profile, region, aws_cli, keep_alive, env: client_env } => {
+            let differences = env_differences(env, &client_env);
+            if !differences.is_empty() {
+                return Response::Error {
+                    message: format!(
+                        "the forward daemon runs with other AWS variables ({}), stop its forwards first",
+                        differences.join(", ")
+                    ),
+                };
+            }
+            let settings = Settings {
+                config: load_profile_config(profile.as_deref(), region.as_deref()).await,
+                aws_cli,
+                keep_alive,
+            };
+            let ids = supervisor.add(forwards, &settings);
+            for forward in supervisor.forwards.iter().filter(|f| ids.contains(&f.id)) {
+                println!("Forward {} started: {} localhost:{}", forward.id, forward.spec.target, forward.spec.local_port);
+            }
+            Response::Started { ids }
+        }
+        Request::List => Response::Forwards {
+            forwards: supervisor
+                .forwards
+                .iter()
+                .map(|forward| ForwardInfo {
+                    id: forward.id,
+                    target: forward.spec.target.clone(),
+                    local_port: forward.spec.local_port,
+                    remote: forward.spec.remote(),
+                    status: forward.status().label(),
+                    uptime: forward.uptime(),
+                    bytes: forward.bytes(),
+                })
+                .collect(),
+        },
+        Request::Stop { id } => {
+            if supervisor.remove(id).await {
+                println!("Forward {} stopped", id);
+                Response::Stopped
+            } else {
+                Response::Error { message: format!("no forward {}", id) }
+            }
+        }
+    }
+}
+
+// Requests read by the connection tasks, handled one at a time by the daemon loop
+type Command = (Request, oneshot::Sender<Response>);
+
+async fn serve(stream: UnixStream, commands: mpsc::Sender<Command>) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, BufReader::new(reader).read_line(&mut line))
+        .await
+        .map_err(|_| "request timeout".to_string())?
+        .map_err(|error| error.to_string())?;
+    // Client gone before sending its request
+    if line.is_empty() {
+        return Ok(());
+    }
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let (reply, response) = oneshot::channel();
+            commands.send((request, reply)).await.map_err(|_| "the daemon is stopping".to_string())?;
+            response.await.map_err(|_| "the daemon is stopping".to_string())?
+        }
+        Err(error) => Response::Error { message: format!("invalid request: {}", error) },
+    };
+    let mut line = serde_json::to_string(&response).unwrap();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await.map_err(|error| error.to_string())
+}
+
+// Owns the background forwards, exits once none is running
+pub(crate) async fn run_daemon() {
+    let path = socket_path();
+    if let Err(error) = create_runtime_dir() {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+    let _lock = match lock_daemon() {
+        Ok(lock) => lock,
+        Err(error) => {
+            println!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    if UnixStream::connect(&path).await.is_ok() {
+        println!("A forward daemon is already running on {}", path.display());
+        std::process::exit(1);
+    }
+    // Socket left by a daemon that didn't stop cleanly
+    let _ = std::fs::remove_file(&path);
+    // Created with its final mode, clients refuse a socket others could use
+    let umask = unsafe { libc::umask(0o777 & !SOCKET_MODE) };
+    let listener = UnixListener::bind(&path);
+    unsafe { libc::umask(umask) };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(error) => {
+            println!("Can't listen on {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+    };
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    println!("Forward daemon listening on {}", path.display());
+
+    let mut supervisor = Supervisor::default();
+    let (commands, mut requests) = mpsc::channel::<Command>(16);
+    let mut connections = JoinSet::new();
+    let env = aws_env();
+    // Started for a request, it isn't kept around if that request never comes
+    let idle = tokio::time::Instant::now() + REQUEST_TIMEOUT;
+    // Sessions without --keep-alive end by themselves, their forwards don't keep the daemon up
+    let mut reap = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else { continue };
+                // A slow or silent client only holds its own task
+                let commands = commands.clone();
+                connections.spawn(async move {
+                    if let Err(error) = serve(stream, commands).await {
+                        println!("Request failed: {}", error);
+                    }
+                });
+            }
+            Some(_) = connections.join_next() => {}
+            Some((request, reply)) = requests.recv() => {
+                let list = matches!(request, Request::List);
+                let _ = reply.send(handle(request, &mut supervisor, &env).await);
+                // The start failed or the last forward was stopped
+                if !list && !supervisor.running() {
+                    break;
+                }
+            }
+            _ = reap.tick() => {
+                for forward in supervisor.reap() {
+                    println!("Forward {} ended: {}", forward.id, forward.status().label());
+                }
+                if !supervisor.forwards.is_empty() && !supervisor.running() {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep_until(idle), if !supervisor.running() => break,
+            _ = hangup.recv() => {}
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    supervisor.stop_all().await;
+    let _ = std::fs::remove_file(&path);
+    // Let the answers already handled reach their clients
+    drop((commands, requests));
+    let _ = tokio::time::timeout(Duration::from_secs(1), async { while connections.join_next().await.is_some() {} }).await;
+    println!("Forward daemon stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // Directory of the test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("devops-cli-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::DirBuilder::new().mode(DIR_MODE).create(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn env_differences_names_changed_variables() {
+        let ours = env(&[("AWS_PROFILE", "prod"), ("AWS_REGION", "eu-west-1")]);
+        assert!(env_differences(&ours, &ours.clone()).is_empty());
+        let theirs = env(&[("AWS_PROFILE", "dev"), ("AWS_REGION", "eu-west-1"), ("AWS_SESSION_TOKEN", "secret")]);
+        assert_eq!(env_differences(&ours, &theirs), vec!["AWS_PROFILE", "AWS_SESSION_TOKEN"]);
+        assert_eq!(env_differences(&ours, &env(&[])), vec!["AWS_PROFILE", "AWS_REGION"]);
+    }
+
+    #[test]
+    fn check_private_accepts_a_private_directory_and_socket() {
+        let dir = TempDir::new("private");
+        assert_eq!(check_private(&dir.0, DIR_MODE), Ok(()));
+
+        let path = dir.0.join("forward.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(SOCKET_MODE)).unwrap();
+        assert_eq!(check_private(&path, SOCKET_MODE), Ok(()));
+    }
+
+    #[test]
+    fn check_private_refuses_open_modes_and_wrong_types() {
+        let dir = TempDir::new("modes");
+        std::fs::set_permissions(&dir.0, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_private(&dir.0, DIR_MODE).unwrap_err().contains("refusing"));
+        std::fs::set_permissions(&dir.0, std::fs::Permissions::from_mode(DIR_MODE)).unwrap();
+
+        let path = dir.0.join("forward.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(check_private(&path, SOCKET_MODE).is_err());
+
+        // A regular file isn't a socket, a socket isn't a directory
+        let file = dir.0.join("forward.log");
+        std::fs::write(&file, "").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(SOCKET_MODE)).unwrap();
+        assert!(check_private(&file, SOCKET_MODE).is_err());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(DIR_MODE)).unwrap();
+        assert!(check_private(&path, DIR_MODE).is_err());
+        assert!(check_private(&dir.0.join("missing"), DIR_MODE).is_err());
+    }
+
+    #[test]
+    fn check_private_refuses_other_owners() {
+        let dir = TempDir::new("owner");
+        // Only root can give the directory away
+        if std::os::unix::fs::chown(&dir.0, Some(65534), None).is_ok() {
+            assert!(check_private(&dir.0, DIR_MODE).is_err());
+        }
+    }
+
+    #[test]
+    fn check_private_doesnt_follow_symlinks() {
+        let dir = TempDir::new("symlink");
+        let target = dir.0.join("target");
+        std::fs::DirBuilder::new().mode(DIR_MODE).create(&target).unwrap();
+        let link = dir.0.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert_eq!(check_private(&target, DIR_MODE), Ok(()));
+        assert!(check_private(&link, DIR_MODE).is_err());
+    }
+}
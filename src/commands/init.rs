@@ -1,10 +1,14 @@
+use crate::commands::aws_utils::{load_config_in_region, resolve_region};
+use crate::commands::cli_utils::{arg_or_prompt, is_interactive};
+use crate::commands::inti_aws_state::{
+    ensure_backend, prompt_bucket_name, prompt_table_name, LockingMode, StateBucketSettings,
+};
 use colored::Colorize;
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::{ContentArrangement, Table};
 use promkit::preset::confirm::Confirm;
 use promkit::preset::readline::Readline;
-use promkit::suggest::Suggest;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
@@ -16,31 +20,20 @@ struct InitArgs {
     accounts: Vec<String>,
     region: String,
     path: String,
+    state_bucket: String,
+    locking: LockingMode,
+    lock_table: Option<String>,
+    create_backend: bool,
     status: bool,
 }
 
-const AWS_REGION: [&str; 20] = [
-    "us-east-1",
-    "us-east-2",
-    "us-west-1",
-    "us-west-2",
-    "ap-south-1",
-    "ap-northeast-1",
-    "ap-northeast-2",
-    "ap-southeast-1",
-    "ap-southeast-2",
-    "ca-central-1",
-    "eu-central-1",
-    "eu-west-1",
-    "eu-west-2",
-    "eu-west-3",
-    "eu-north-1",
-    "sa-east-1",
-    "cn-north-1",
-    "cn-northwest-1",
-    "us-gov-east-1",
-    "us-gov-west-1",
-];
+// Where the generated backend.tf files store the terraform states
+struct Backend<'a> {
+    bucket: &'a str,
+    table: Option<&'a str>,
+    locking: LockingMode,
+    region: &'a str,
+}
 
 fn create_main_file(path: &str) -> std::io::Result<()> {
     let main_content: &str = "\
@@ -109,19 +102,28 @@ variable \"region\" {
     Ok(())
 }
 
-fn create_backend_file(path: &str, region: &str) -> std::io::Result<()> {
+fn create_backend_file(path: &str, backend: &Backend, key: &str) -> std::io::Result<()> {
     let mut tera = Tera::default();
     let mut context = Context::new();
-    context.insert("region", region);
+    context.insert("bucket", backend.bucket);
+    context.insert("key", key);
+    context.insert("region", backend.region);
+    context.insert("table", &backend.table);
+    context.insert("lockfile", &backend.locking.uses_lockfile());
     let backend_content = tera
         .render_str(
             "\
 terraform {
     backend \"s3\" {
-        bucket = \"mybucket\"
-        key    = \"path/to/my/key\"
+        bucket = \"{{ bucket }}\"
+        key    = \"{{ key }}\"
         region = \"{{ region }}\"
-        dynamodb_table = \"tfstate-lock\"
+{%- if table %}
+        dynamodb_table = \"{{ table }}\"
+{%- endif %}
+{%- if lockfile %}
+        use_lockfile   = true
+{%- endif %}
         encrypt        = true
     }
 }",
@@ -153,93 +155,122 @@ fn init_folders(root: &str) -> std::io::Result<()> {
     Ok(())
 }
 
-fn display_prompt() -> InitArgs {
+fn prompt_project() -> String {
     let mut project = Readline::default()
         .title("What is the name of the project ?")
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("You should put a name {}", text.len()),
         )
         .prompt()
         .unwrap();
     let project_string = project.run();
-    let project_string = match project_string {
+    match project_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
+fn prompt_environments() -> String {
     let mut environments = Readline::default()
         .title("Which environment do you want ? (If you want multiple environments, separate them by a comma)")
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("You should at least enter one environment name {}", text.len()),
         )
         .prompt().unwrap();
     let environments_string = environments.run();
-    let environments_string = match environments_string {
+    match environments_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
+fn prompt_accounts() -> String {
     let mut accounts = Readline::default()
         .title(
             "Which account do you want ? (If you want multiple accounts, separate them by a comma)",
         )
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("You should at least enter one account name {}", text.len()),
         )
         .prompt()
         .unwrap();
     let accounts_string = accounts.run();
-    let accounts_string = match accounts_string {
+    match accounts_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    let mut region = Readline::default()
-        .title("Which region should I use ? (Press tab to see the list of available regions)")
-        .enable_suggest(Suggest::from_iter(AWS_REGION))
-        .validator(
-            |text| AWS_REGION.contains(&text),
-            |text| format!("You should enter a valid region {}", text),
-        )
+fn prompt_path() -> String {
+    let mut path = Readline::default()
+        .title("Where should I create the repository ? (Default: current directory)")
         .prompt()
         .unwrap();
-    let region_string = region.run();
-    let region_string = match region_string {
+    let path_string = path.run();
+    match path_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    let mut path = Readline::default()
-        .title("Where should I create the repository ? (Default: current directory)")
+fn prompt_create_backend() -> bool {
+    let mut confirm = Confirm::new("Should I create the state bucket and lock table (or check them if they exist) ?")
         .prompt()
         .unwrap();
-    let path_string = path.run();
-    let mut path_string = match path_string {
+    let confirm_string = confirm.run();
+    let confirm_string = match confirm_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
     };
+    confirm_string == "yes" || confirm_string == "y"
+}
+
+async fn display_prompt(matches: &clap::ArgMatches) -> InitArgs {
+    let project_string = arg_or_prompt(matches, "project", prompt_project);
+    let environments_string = arg_or_prompt(matches, "environments", prompt_environments);
+    let accounts_string = arg_or_prompt(matches, "accounts", prompt_accounts);
+    let region_string = resolve_region(matches).await;
+    // The current directory is the default, no need to ask for it in scripts
+    let mut path_string = match matches.get_one::<String>("path") {
+        Some(path) => path.clone(),
+        None if is_interactive() => prompt_path(),
+        None => String::new(),
+    };
     if path_string.is_empty() {
         path_string = "./".to_string();
     }
-    drop(path);
+    let state_bucket_string = arg_or_prompt(matches, "state-bucket", prompt_bucket_name);
+    let locking = LockingMode::from_matches(matches);
+    let lock_table_string = if locking.uses_table() {
+        Some(arg_or_prompt(matches, "lock-table", prompt_table_name))
+    } else {
+        None
+    };
+    let create_backend = if matches.get_flag("create-backend") {
+        true
+    } else if is_interactive() {
+        prompt_create_backend()
+    } else {
+        false
+    };
 
     let mut table = Table::new();
     table
@@ -250,21 +281,30 @@ fn display_prompt() -> InitArgs {
         .add_row(vec!["Project", project_string.as_str()])
         .add_row(vec!["Path", path_string.as_str()])
         .add_row(vec!["Environments", environments_string.as_str()])
-        .add_row(vec!["Accounts", accounts_string.as_str()]);
+        .add_row(vec!["Accounts", accounts_string.as_str()])
+        .add_row(vec!["State bucket", state_bucket_string.as_str()])
+        .add_row(vec!["Locking", locking.as_str()])
+        .add_row(vec!["Lock table", lock_table_string.as_deref().unwrap_or("-")])
+        .add_row(vec!["Create/check backend", if create_backend { "yes" } else { "no" }]);
 
     println!("{}", table);
 
-    let mut confirm = Confirm::new("Is this correct ?").prompt().unwrap();
-    let confirm_string = confirm.run();
-    let confirm_string = match confirm_string {
-        Ok(value) => value,
-        Err(_) => {
-            print!("Aborted by user");
-            std::process::exit(1);
-        }
+    // Everything was given on the command line, nothing to confirm
+    let status = if matches.get_flag("yes") || !is_interactive() {
+        true
+    } else {
+        let mut confirm = Confirm::new("Is this correct ?").prompt().unwrap();
+        let confirm_string = confirm.run();
+        let confirm_string = match confirm_string {
+            Ok(value) => value,
+            Err(_) => {
+                print!("Aborted by user");
+                std::process::exit(1);
+            }
+        };
+        drop(confirm);
+        confirm_string == "yes" || confirm_string == "y"
     };
-    let status = confirm_string == "yes" || confirm_string == "y";
-    drop(confirm);
 
     InitArgs {
         project: project_string,
@@ -278,12 +318,16 @@ fn display_prompt() -> InitArgs {
             .collect(),
         region: region_string,
         path: path_string,
+        state_bucket: state_bucket_string,
+        locking,
+        lock_table: lock_table_string,
+        create_backend,
         status,
     }
 }
 
-pub fn init() {
-    let args = display_prompt();
+pub async fn init(matches: &clap::ArgMatches) {
+    let args = display_prompt(matches).await;
     if !args.status {
         println!("Aborted by user");
         return;
@@ -296,6 +340,22 @@ pub fn init() {
     if !path.ends_with("/") {
         path = format!("{}/", path);
     }
+    let backend = Backend {
+        bucket: args.state_bucket.as_str(),
+        table: args.lock_table.as_deref(),
+        locking: args.locking,
+        region: region.as_str(),
+    };
+    if args.create_backend {
+        let config = load_config_in_region(matches, Some(backend.region)).await;
+        let settings = StateBucketSettings::default();
+        if !ensure_backend(&config, backend.bucket, backend.table, &settings, false).await {
+            println!(
+                "{} the state backend isn't ready, backend.tf files are generated anyway",
+                "Warning:".yellow().bold()
+            );
+        }
+    }
 
     let folders_status = init_folders(path.as_str());
     if folders_status.is_err() {
@@ -314,7 +374,8 @@ pub fn init() {
         }
         let backend_status = create_backend_file(
             format!("{}environments/{}/", path.as_str(), environment).as_str(),
-            region.as_str(),
+            &backend,
+            format!("{}/environments/{}/terraform.tfstate", project, environment).as_str(),
         );
         if backend_status.is_err() {
             println!(
@@ -376,7 +437,8 @@ pub fn init() {
         }
         let backend_status = create_backend_file(
             format!("{}accounts/{}/", path.as_str(), account).as_str(),
-            region.as_str(),
+            &backend,
+            format!("{}/accounts/{}/terraform.tfstate", project, account).as_str(),
         );
         if backend_status.is_err() {
             println!(
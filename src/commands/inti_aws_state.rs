@@ -1,49 +1,50 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_config::Region;
+use crate::commands::cli_utils::{arg_or_prompt, is_interactive};
+use crate::commands::aws_utils::{error_message, load_config_in_region, resolve_region, S3Clients};
+use aws_config::SdkConfig;
 use aws_sdk_dynamodb::types::{
-    AttributeDefinition, KeySchemaElement, KeyType, ScalarAttributeType,
+    AttributeDefinition, BillingMode, KeySchemaElement, KeyType, ScalarAttributeType,
+    TableDescription,
 };
 use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::types::{
+    AbortIncompleteMultipartUpload, BucketLifecycleConfiguration, BucketVersioningStatus,
+    ExpirationStatus, LifecycleRule, LifecycleRuleFilter, NoncurrentVersionExpiration,
+    ObjectOwnership, OwnershipControls, OwnershipControlsRule, PublicAccessBlockConfiguration,
+    ServerSideEncryption, ServerSideEncryptionByDefault, ServerSideEncryptionConfiguration,
+    ServerSideEncryptionRule, VersioningConfiguration,
+};
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use promkit::preset::confirm::Confirm;
+use promkit::preset::listbox::Listbox;
 use promkit::preset::readline::Readline;
-use promkit::suggest::Suggest;
-
-const AWS_REGION: [&str; 20] = [
-    "us-east-1",
-    "us-east-2",
-    "us-west-1",
-    "us-west-2",
-    "ap-south-1",
-    "ap-northeast-1",
-    "ap-northeast-2",
-    "ap-southeast-1",
-    "ap-southeast-2",
-    "ca-central-1",
-    "eu-central-1",
-    "eu-west-1",
-    "eu-west-2",
-    "eu-west-3",
-    "eu-north-1",
-    "sa-east-1",
-    "cn-north-1",
-    "cn-northwest-1",
-    "us-gov-east-1",
-    "us-gov-west-1",
-];
-
-async fn create_bucket(bucket_name: &str, region: String) -> bool {
-    let region_provider = RegionProviderChain::first_try(Region::new(region.clone()));
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client = aws_sdk_s3::Client::new(&config);
-
-    let s3_cfg = aws_sdk_s3::types::CreateBucketConfiguration::builder()
-        .location_constraint(aws_sdk_s3::types::BucketLocationConstraint::from(
-            region.as_str(),
-        ))
-        .build();
+use serde_json::{json, Value};
+
+const NONCURRENT_RULE_ID: &str = "expire-noncurrent-state-versions";
+const TLS_STATEMENT_ID: &str = "DenyInsecureTransport";
+
+async fn create_bucket(config: &SdkConfig, bucket_name: &str) -> bool {
+    let client = aws_sdk_s3::Client::new(config);
+    let region = config.region().map(|r| r.to_string()).unwrap_or_default();
+
+    // us-east-1 is the default location and is rejected as a location constraint
+    let s3_cfg = if region == "us-east-1" {
+        None
+    } else {
+        Some(
+            aws_sdk_s3::types::CreateBucketConfiguration::builder()
+                .location_constraint(aws_sdk_s3::types::BucketLocationConstraint::from(
+                    region.as_str(),
+                ))
+                .build(),
+        )
+    };
 
     let response = client
         .create_bucket()
-        .create_bucket_configuration(s3_cfg)
+        .set_create_bucket_configuration(s3_cfg)
         .bucket(bucket_name)
         .send()
         .await;
@@ -54,15 +55,596 @@ async fn create_bucket(bucket_name: &str, region: String) -> bool {
             true
         }
         Err(error) => {
-            println!("Failed to create S3 bucket: \n {:?}", error);
+            println!("Failed to create S3 bucket:\n {}", error_message(&error));
             false
         }
     }
 }
 
-pub async fn create_table(table: &str, key: &str) -> bool {
-    let config = aws_config::load_from_env().await;
-    let client = aws_sdk_dynamodb::Client::new(&config);
+// How terraform locks the states: a DynamoDB table, a lockfile next to the state in the
+// bucket (`use_lockfile`, terraform >= 1.10), or both while migrating from one to the other
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum LockingMode {
+    DynamoDB,
+    Lockfile,
+    Both,
+}
+
+const LOCKING_MODES: [&str; 3] = ["dynamodb", "lockfile", "both"];
+
+impl LockingMode {
+    fn parse(value: &str) -> Self {
+        match value {
+            "lockfile" => LockingMode::Lockfile,
+            "both" => LockingMode::Both,
+            _ => LockingMode::DynamoDB,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            LockingMode::DynamoDB => "dynamodb",
+            LockingMode::Lockfile => "lockfile",
+            LockingMode::Both => "both",
+        }
+    }
+
+    pub(crate) fn uses_table(&self) -> bool {
+        *self != LockingMode::Lockfile
+    }
+
+    pub(crate) fn uses_lockfile(&self) -> bool {
+        *self != LockingMode::DynamoDB
+    }
+
+    // --locking, or a prompt. Scripts without the flag keep the DynamoDB table
+    pub(crate) fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.get_one::<String>("locking") {
+            Some(value) => Self::parse(value),
+            None if is_interactive() => Self::parse(&prompt_locking_mode()),
+            None => LockingMode::DynamoDB,
+        }
+    }
+}
+
+// How the state bucket is locked down once created
+pub(crate) struct StateBucketSettings {
+    // Default encryption uses this KMS key, or AES256 when there is none
+    pub(crate) kms_key: Option<String>,
+    // Days before noncurrent state versions expire
+    pub(crate) retention_days: i32,
+}
+
+impl Default for StateBucketSettings {
+    fn default() -> Self {
+        Self {
+            kms_key: None,
+            retention_days: 90,
+        }
+    }
+}
+
+impl StateBucketSettings {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            kms_key: matches.get_one::<String>("kms-key").cloned(),
+            retention_days: *matches.get_one::<i32>("retention").unwrap(),
+        }
+    }
+}
+
+fn report_step<T, E: ProvideErrorMetadata + std::fmt::Debug>(step: &str, result: Result<T, E>) -> bool {
+    match result {
+        Ok(_) => {
+            println!("{} successfully", step);
+            true
+        }
+        Err(error) => {
+            println!("Failed: {}:\n {}", step.to_lowercase(), error_message(&error));
+            false
+        }
+    }
+}
+
+fn is_tls_statement(statement: &Value) -> bool {
+    statement["Effect"] == "Deny" && statement["Condition"]["Bool"]["aws:SecureTransport"] == "false"
+}
+
+// Add the TLS statement to the existing policy, if any, rather than replacing it
+fn tls_only_policy(bucket_name: &str, existing: Option<&str>) -> String {
+    let statement = json!({
+        "Sid": TLS_STATEMENT_ID,
+        "Effect": "Deny",
+        "Principal": "*",
+        "Action": "s3:*",
+        "Resource": [
+            format!("arn:aws:s3:::{}", bucket_name),
+            format!("arn:aws:s3:::{}/*", bucket_name),
+        ],
+        "Condition": { "Bool": { "aws:SecureTransport": "false" } }
+    });
+    let mut policy = existing
+        .and_then(|p| serde_json::from_str::<Value>(p).ok())
+        .unwrap_or_else(|| json!({ "Version": "2012-10-17", "Statement": [] }));
+    match policy["Statement"].as_array_mut() {
+        Some(statements) => {
+            statements.retain(|s| !is_tls_statement(s));
+            statements.push(statement);
+        }
+        None => policy["Statement"] = json!([statement]),
+    }
+    policy.to_string()
+}
+
+async fn get_bucket_policy(client: &aws_sdk_s3::Client, bucket_name: &str) -> Result<Option<String>, String> {
+    match client.get_bucket_policy().bucket(bucket_name).send().await {
+        Ok(output) => Ok(output.policy().map(|p| p.to_string())),
+        Err(error) if error.code() == Some("NoSuchBucketPolicy") => Ok(None),
+        Err(error) => Err(error_message(&error)),
+    }
+}
+
+async fn get_lifecycle_rules(client: &aws_sdk_s3::Client, bucket_name: &str) -> Result<Vec<LifecycleRule>, String> {
+    match client
+        .get_bucket_lifecycle_configuration()
+        .bucket(bucket_name)
+        .send()
+        .await
+    {
+        Ok(output) => Ok(output.rules().to_vec()),
+        Err(error) if error.code() == Some("NoSuchLifecycleConfiguration") => Ok(Vec::new()),
+        Err(error) => Err(error_message(&error)),
+    }
+}
+
+pub(crate) async fn enable_versioning(client: &aws_sdk_s3::Client, bucket_name: &str) -> bool {
+    let versioning = VersioningConfiguration::builder()
+        .status(BucketVersioningStatus::Enabled)
+        .build();
+    let response = client
+        .put_bucket_versioning()
+        .bucket(bucket_name)
+        .versioning_configuration(versioning)
+        .send()
+        .await;
+    report_step("Versioning enabled", response)
+}
+
+pub(crate) async fn enable_encryption(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    settings: &StateBucketSettings,
+) -> bool {
+    let default_encryption = match &settings.kms_key {
+        Some(kms_key) => ServerSideEncryptionByDefault::builder()
+            .sse_algorithm(ServerSideEncryption::AwsKms)
+            .kms_master_key_id(kms_key),
+        None => ServerSideEncryptionByDefault::builder().sse_algorithm(ServerSideEncryption::Aes256),
+    };
+    let rule = ServerSideEncryptionRule::builder()
+        .apply_server_side_encryption_by_default(default_encryption.build().unwrap())
+        .bucket_key_enabled(settings.kms_key.is_some())
+        .build();
+    let encryption = ServerSideEncryptionConfiguration::builder()
+        .rules(rule)
+        .build()
+        .unwrap();
+    let response = client
+        .put_bucket_encryption()
+        .bucket(bucket_name)
+        .server_side_encryption_configuration(encryption)
+        .send()
+        .await;
+    report_step("Default encryption enabled", response)
+}
+
+pub(crate) async fn block_public_access(client: &aws_sdk_s3::Client, bucket_name: &str) -> bool {
+    let block = PublicAccessBlockConfiguration::builder()
+        .block_public_acls(true)
+        .ignore_public_acls(true)
+        .block_public_policy(true)
+        .restrict_public_buckets(true)
+        .build();
+    let response = client
+        .put_public_access_block()
+        .bucket(bucket_name)
+        .public_access_block_configuration(block)
+        .send()
+        .await;
+    report_step("Public access blocked", response)
+}
+
+pub(crate) async fn enforce_bucket_owner(client: &aws_sdk_s3::Client, bucket_name: &str) -> bool {
+    let rule = OwnershipControlsRule::builder()
+        .object_ownership(ObjectOwnership::BucketOwnerEnforced)
+        .build()
+        .unwrap();
+    let ownership = OwnershipControls::builder().rules(rule).build().unwrap();
+    let response = client
+        .put_bucket_ownership_controls()
+        .bucket(bucket_name)
+        .ownership_controls(ownership)
+        .send()
+        .await;
+    report_step("Bucket owner enforced", response)
+}
+
+pub(crate) async fn enforce_tls(client: &aws_sdk_s3::Client, bucket_name: &str) -> bool {
+    let existing = match get_bucket_policy(client, bucket_name).await {
+        Ok(existing) => existing,
+        Err(error) => {
+            println!("Failed to read the bucket policy:\n {}", error);
+            return false;
+        }
+    };
+    let response = client
+        .put_bucket_policy()
+        .bucket(bucket_name)
+        .policy(tls_only_policy(bucket_name, existing.as_deref()))
+        .send()
+        .await;
+    report_step("TLS only bucket policy applied", response)
+}
+
+pub(crate) async fn expire_noncurrent_versions(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    settings: &StateBucketSettings,
+) -> bool {
+    let rule = LifecycleRule::builder()
+        .id(NONCURRENT_RULE_ID)
+        .status(ExpirationStatus::Enabled)
+        .filter(LifecycleRuleFilter::Prefix(String::new()))
+        .noncurrent_version_expiration(
+            NoncurrentVersionExpiration::builder()
+                .noncurrent_days(settings.retention_days)
+                .build(),
+        )
+        .abort_incomplete_multipart_upload(
+            AbortIncompleteMultipartUpload::builder()
+                .days_after_initiation(7)
+                .build(),
+        )
+        .build()
+        .unwrap();
+    // Keep the other rules of the bucket, replace ours
+    let mut rules = match get_lifecycle_rules(client, bucket_name).await {
+        Ok(rules) => rules,
+        Err(error) => {
+            println!("Failed to read the lifecycle rules:\n {}", error);
+            return false;
+        }
+    };
+    rules.retain(|r| r.id() != Some(NONCURRENT_RULE_ID));
+    rules.push(rule);
+    let lifecycle = BucketLifecycleConfiguration::builder()
+        .set_rules(Some(rules))
+        .build()
+        .unwrap();
+    let response = client
+        .put_bucket_lifecycle_configuration()
+        .bucket(bucket_name)
+        .lifecycle_configuration(lifecycle)
+        .send()
+        .await;
+    report_step(
+        &format!("Noncurrent versions expire after {} days", settings.retention_days),
+        response,
+    )
+}
+
+// Everything a terraform state bucket needs on top of a bare bucket
+async fn harden_bucket(config: &SdkConfig, bucket_name: &str, settings: &StateBucketSettings) -> bool {
+    let client = aws_sdk_s3::Client::new(config);
+    enable_versioning(&client, bucket_name).await
+        && enable_encryption(&client, bucket_name, settings).await
+        && block_public_access(&client, bucket_name).await
+        && enforce_bucket_owner(&client, bucket_name).await
+        && enforce_tls(&client, bucket_name).await
+        && expire_noncurrent_versions(&client, bucket_name, settings).await
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fix {
+    Versioning,
+    Encryption,
+    PublicAccess,
+    Ownership,
+    TlsPolicy,
+    Lifecycle,
+    BillingMode,
+}
+
+// One setting of the backend compared to what init-aws-state would have created
+struct Check {
+    resource: String,
+    setting: &'static str,
+    expected: String,
+    actual: String,
+    ok: bool,
+    // None when the drift can't be fixed in place
+    fix: Option<Fix>,
+}
+
+impl Check {
+    fn new(resource: &str, setting: &'static str, expected: &str, actual: String, ok: bool, fix: Option<Fix>) -> Self {
+        Self {
+            resource: resource.to_string(),
+            setting,
+            expected: expected.to_string(),
+            actual,
+            ok,
+            fix,
+        }
+    }
+}
+
+pub(crate) async fn bucket_exists(client: &aws_sdk_s3::Client, bucket_name: &str) -> Result<bool, String> {
+    match client.head_bucket().bucket(bucket_name).send().await {
+        Ok(_) => Ok(true),
+        Err(error) => match error.raw_response().map(|r| r.status().as_u16()) {
+            Some(404) => Ok(false),
+            // The bucket exists in another region
+            Some(301) => Ok(true),
+            Some(403) => Err("the bucket belongs to another account or access is denied".to_string()),
+            _ => Err(error_message(&error)),
+        },
+    }
+}
+
+pub(crate) async fn describe_table(
+    client: &aws_sdk_dynamodb::Client,
+    table: &str,
+) -> Result<Option<TableDescription>, String> {
+    match client.describe_table().table_name(table).send().await {
+        Ok(output) => Ok(output.table().cloned()),
+        Err(error) if error.code() == Some("ResourceNotFoundException") => Ok(None),
+        Err(error) => Err(error_message(&error)),
+    }
+}
+
+// ARN of a key given by ID, ARN, alias name or alias ARN
+async fn kms_key_arn(kms: &aws_sdk_kms::Client, key: &str) -> Result<String, String> {
+    let output = kms.describe_key().key_id(key).send().await.map_err(|error| error_message(&error))?;
+    output
+        .key_metadata()
+        .and_then(|metadata| metadata.arn())
+        .map(|arn| arn.to_string())
+        .ok_or_else(|| format!("no ARN for key {}", key))
+}
+
+// S3 keeps the key the way it was set, an alias and the ARN of its key are the same key.
+// Without a key, the bucket uses the AWS managed key of S3
+async fn same_kms_key(kms: &aws_sdk_kms::Client, key: &str, expected: &str) -> bool {
+    let key = if key.is_empty() { "alias/aws/s3" } else { key };
+    if key == expected {
+        return true;
+    }
+    match (kms_key_arn(kms, key).await, kms_key_arn(kms, expected).await) {
+        (Ok(key), Ok(expected)) => key == expected,
+        _ => false,
+    }
+}
+
+// Expiration set by the rule of the tool, the other rules of the bucket don't count
+fn noncurrent_expiration(rules: &[LifecycleRule]) -> String {
+    rules
+        .iter()
+        .find(|r| r.id() == Some(NONCURRENT_RULE_ID) && *r.status() == ExpirationStatus::Enabled)
+        .and_then(|r| r.noncurrent_version_expiration())
+        .and_then(|e| e.noncurrent_days())
+        .map(|days| format!("{} days", days))
+        .unwrap_or("None".to_string())
+}
+
+async fn audit_bucket(
+    client: &aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    bucket_name: &str,
+    settings: &StateBucketSettings,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let versioning = match client.get_bucket_versioning().bucket(bucket_name).send().await {
+        Ok(output) => output.status().map(|s| s.as_str().to_string()).unwrap_or("Disabled".to_string()),
+        Err(error) => format!("error: {}", error_message(&error)),
+    };
+    checks.push(Check::new(bucket_name, "Versioning", "Enabled", versioning.clone(), versioning == "Enabled", Some(Fix::Versioning)));
+
+    let expected_encryption = match &settings.kms_key {
+        Some(kms_key) => format!("aws:kms ({})", kms_key),
+        None => "AES256 or aws:kms".to_string(),
+    };
+    let (encryption, encryption_ok) = match client.get_bucket_encryption().bucket(bucket_name).send().await {
+        Ok(output) => {
+            let default = output
+                .server_side_encryption_configuration()
+                .and_then(|c| c.rules().first())
+                .and_then(|r| r.apply_server_side_encryption_by_default());
+            match default {
+                Some(default) => {
+                    let key = default.kms_master_key_id().unwrap_or_default();
+                    let ok = match &settings.kms_key {
+                        Some(kms_key) => {
+                            *default.sse_algorithm() == ServerSideEncryption::AwsKms
+                                && same_kms_key(kms, key, kms_key).await
+                        }
+                        None => true,
+                    };
+                    if key.is_empty() {
+                        (default.sse_algorithm().as_str().to_string(), ok)
+                    } else {
+                        (format!("{} ({})", default.sse_algorithm().as_str(), key), ok)
+                    }
+                }
+                None => ("None".to_string(), false),
+            }
+        }
+        Err(error) if error.code() == Some("ServerSideEncryptionConfigurationNotFoundError") => ("None".to_string(), false),
+        Err(error) => (format!("error: {}", error_message(&error)), false),
+    };
+    checks.push(Check::new(bucket_name, "Default encryption", &expected_encryption, encryption, encryption_ok, Some(Fix::Encryption)));
+
+    let (public_access, public_access_ok) = match client.get_public_access_block().bucket(bucket_name).send().await {
+        Ok(output) => {
+            let block = output.public_access_block_configuration();
+            let flags = [
+                ("BlockPublicAcls", block.and_then(|b| b.block_public_acls())),
+                ("IgnorePublicAcls", block.and_then(|b| b.ignore_public_acls())),
+                ("BlockPublicPolicy", block.and_then(|b| b.block_public_policy())),
+                ("RestrictPublicBuckets", block.and_then(|b| b.restrict_public_buckets())),
+            ];
+            let missing: Vec<&str> = flags.iter().filter(|(_, v)| *v != Some(true)).map(|(n, _)| *n).collect();
+            if missing.is_empty() {
+                ("All blocked".to_string(), true)
+            } else {
+                (format!("Missing {}", missing.join(", ")), false)
+            }
+        }
+        Err(error) if error.code() == Some("NoSuchPublicAccessBlockConfiguration") => ("None".to_string(), false),
+        Err(error) => (format!("error: {}", error_message(&error)), false),
+    };
+    checks.push(Check::new(bucket_name, "Public access block", "All blocked", public_access, public_access_ok, Some(Fix::PublicAccess)));
+
+    let ownership = match client.get_bucket_ownership_controls().bucket(bucket_name).send().await {
+        Ok(output) => output
+            .ownership_controls()
+            .and_then(|c| c.rules().first())
+            .map(|r| r.object_ownership().as_str().to_string())
+            .unwrap_or("None".to_string()),
+        Err(error) if error.code() == Some("OwnershipControlsNotFoundError") => "None".to_string(),
+        Err(error) => format!("error: {}", error_message(&error)),
+    };
+    let ownership_ok = ownership == ObjectOwnership::BucketOwnerEnforced.as_str();
+    checks.push(Check::new(bucket_name, "Object ownership", "BucketOwnerEnforced", ownership, ownership_ok, Some(Fix::Ownership)));
+
+    let (policy, policy_ok) = match get_bucket_policy(client, bucket_name).await {
+        Ok(Some(policy)) => {
+            let policy: Value = serde_json::from_str(&policy).unwrap_or_default();
+            let tls_only = policy["Statement"]
+                .as_array()
+                .map(|statements| statements.iter().any(is_tls_statement))
+                .unwrap_or(false);
+            if tls_only {
+                ("TLS only".to_string(), true)
+            } else {
+                ("No TLS statement".to_string(), false)
+            }
+        }
+        Ok(None) => ("None".to_string(), false),
+        Err(error) => (format!("error: {}", error), false),
+    };
+    checks.push(Check::new(bucket_name, "Bucket policy", "TLS only", policy, policy_ok, Some(Fix::TlsPolicy)));
+
+    let expected_lifecycle = format!("{} days", settings.retention_days);
+    let lifecycle = match get_lifecycle_rules(client, bucket_name).await {
+        Ok(rules) => noncurrent_expiration(&rules),
+        Err(error) => format!("error: {}", error),
+    };
+    checks.push(Check::new(bucket_name, "Noncurrent versions expiration", &expected_lifecycle, lifecycle.clone(), lifecycle == expected_lifecycle, Some(Fix::Lifecycle)));
+
+    checks
+}
+
+fn audit_table(table: &str, description: &TableDescription, key: &str) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let hash_key = description
+        .key_schema()
+        .iter()
+        .find(|k| *k.key_type() == KeyType::Hash)
+        .map(|k| k.attribute_name().to_string())
+        .unwrap_or_default();
+    let hash_key_type = description
+        .attribute_definitions()
+        .iter()
+        .find(|a| a.attribute_name() == hash_key)
+        .map(|a| a.attribute_type().as_str().to_string())
+        .unwrap_or_default();
+    let range_key = description.key_schema().iter().any(|k| *k.key_type() == KeyType::Range);
+    let actual = format!("{} ({}){}", hash_key, hash_key_type, if range_key { " with a range key" } else { "" });
+    let ok = hash_key == key && hash_key_type == "S" && !range_key;
+    checks.push(Check::new(table, "Hash key", &format!("{} (S)", key), actual, ok, None));
+
+    // Tables created before on-demand existed have no billing mode summary
+    let billing_mode = description
+        .billing_mode_summary()
+        .and_then(|b| b.billing_mode())
+        .map(|b| b.as_str().to_string())
+        .unwrap_or(BillingMode::Provisioned.as_str().to_string());
+    let ok = billing_mode == BillingMode::PayPerRequest.as_str();
+    checks.push(Check::new(table, "Billing mode", BillingMode::PayPerRequest.as_str(), billing_mode, ok, Some(Fix::BillingMode)));
+
+    checks
+}
+
+fn drift_table(checks: &[Check]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Resource", "Setting", "Expected", "Actual", "Status"]);
+    for check in checks {
+        let status = match (check.ok, check.fix) {
+            (true, _) => "OK".green().to_string(),
+            (false, Some(_)) => "Drift".yellow().to_string(),
+            (false, None) => "Drift (can't be fixed)".red().to_string(),
+        };
+        table.add_row(vec![
+            check.resource.clone(),
+            check.setting.to_string(),
+            check.expected.clone(),
+            check.actual.clone(),
+            status,
+        ]);
+    }
+    table
+}
+
+async fn apply_fix(
+    s3: &aws_sdk_s3::Client,
+    dynamo: &aws_sdk_dynamodb::Client,
+    check: &Check,
+    settings: &StateBucketSettings,
+) -> bool {
+    let resource = check.resource.as_str();
+    match check.fix {
+        Some(Fix::Versioning) => enable_versioning(s3, resource).await,
+        Some(Fix::Encryption) => enable_encryption(s3, resource, settings).await,
+        Some(Fix::PublicAccess) => block_public_access(s3, resource).await,
+        Some(Fix::Ownership) => enforce_bucket_owner(s3, resource).await,
+        Some(Fix::TlsPolicy) => enforce_tls(s3, resource).await,
+        Some(Fix::Lifecycle) => expire_noncurrent_versions(s3, resource, settings).await,
+        Some(Fix::BillingMode) => {
+            let response = dynamo
+                .update_table()
+                .table_name(resource)
+                .billing_mode(BillingMode::PayPerRequest)
+                .send()
+                .await;
+            report_step("Billing mode switched to PAY_PER_REQUEST", response)
+        }
+        None => false,
+    }
+}
+
+fn confirm_fix(count: usize) -> bool {
+    let mut confirm = Confirm::new(format!("Fix the {} drifted settings ?", count)).prompt().unwrap();
+    let confirm_string = confirm.run();
+    let confirm_string = match confirm_string {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    drop(confirm);
+    confirm_string == "yes" || confirm_string == "y"
+}
+
+pub async fn create_table(config: &SdkConfig, table: &str, key: &str) -> bool {
+    let client = aws_sdk_dynamodb::Client::new(config);
     let a_name: String = key.into();
     let table_name: String = table.into();
 
@@ -93,77 +675,247 @@ pub async fn create_table(table: &str, key: &str) -> bool {
             true
         }
         Err(error) => {
-            println!(
-                "Failed to create dynamoDB table:\n {:?}",
-                error.message().unwrap()
-            );
+            println!("Failed to create dynamoDB table:\n {}", error_message(&error));
             false
         }
     }
 }
 
-pub async fn init_aws_state() {
+pub(crate) fn prompt_bucket_name() -> String {
     let mut bucket_name = Readline::default()
         .title("How do you want to name the bucket?")
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("Your bucket name can't be empty {}", text.len()),
         )
         .prompt()
         .unwrap();
     let bucket_name_string = bucket_name.run();
-    let bucket_name_string = match bucket_name_string {
+    match bucket_name_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
+pub(crate) fn prompt_table_name() -> String {
     let mut dynamo = Readline::default()
         .title("How do you want to name the dynamoDB ?")
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("Your dynamoDB name can't be empty {}", text.len()),
         )
         .prompt()
         .unwrap();
     let dynamo_string = dynamo.run();
-    let dynamo_string = match dynamo_string {
+    match dynamo_string {
         Ok(value) => value,
         Err(_) => {
             print!("Aborted by user");
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    let mut region = Readline::default()
-        .title("Which region should I use ? (Press tab to see the list of available regions)")
-        .enable_suggest(Suggest::from_iter(AWS_REGION))
-        .validator(
-            |text| AWS_REGION.contains(&text),
-            |text| format!("You should enter a valid region {}", text),
-        )
+fn prompt_locking_mode() -> String {
+    Listbox::new(LOCKING_MODES)
+        .title("How should terraform lock the states? (dynamodb table, S3 lockfile, or both while migrating)")
+        .listbox_lines(5)
         .prompt()
-        .unwrap();
-    let region_string = region.run();
-    let region_string = match region_string {
-        Ok(value) => value,
-        Err(_) => {
-            print!("Aborted by user");
-            std::process::exit(1);
+        .unwrap()
+        .run()
+        .unwrap()
+}
+
+// Create the state bucket and lock table (when there is one), or audit them when they already
+// exist. Returns false when something failed or still drifts
+pub(crate) async fn ensure_backend(
+    config: &SdkConfig,
+    bucket_name: &str,
+    table_name: Option<&str>,
+    settings: &StateBucketSettings,
+    fix: bool,
+) -> bool {
+    let region = config.region().map(|r| r.to_string()).unwrap_or_default();
+    let clients = S3Clients::new(config);
+    let dynamo = aws_sdk_dynamodb::Client::new(config);
+    let mut checks: Vec<Check> = Vec::new();
+
+    // Existing resources are audited instead of created, so running the command twice is safe
+    match bucket_exists(&clients.default_client(), bucket_name).await {
+        Ok(false) => {
+            if !create_bucket(config, bucket_name).await {
+                println!("\nFailed to create bucket");
+                return false;
+            }
+            if !harden_bucket(config, bucket_name, settings).await {
+                println!("\nFailed to configure bucket");
+                return false;
+            }
         }
+        Ok(true) => {
+            println!("S3 bucket {} already exists, checking its configuration", bucket_name.bold());
+            let bucket_region = clients.bucket_region(bucket_name).await.unwrap_or_default();
+            checks.push(Check::new(
+                bucket_name,
+                "Region",
+                &region,
+                bucket_region.clone(),
+                bucket_region == region,
+                None,
+            ));
+            let client = clients.for_bucket(bucket_name).await;
+            // Encryption keys live in the region of the bucket
+            let mut kms_config = aws_sdk_kms::config::Builder::from(config);
+            if !bucket_region.is_empty() {
+                kms_config = kms_config.region(aws_sdk_kms::config::Region::new(bucket_region.clone()));
+            }
+            let kms = aws_sdk_kms::Client::from_conf(kms_config.build());
+            checks.extend(audit_bucket(&client, &kms, bucket_name, settings).await);
+        }
+        Err(error) => {
+            println!("Failed to check S3 bucket {}:\n {}", bucket_name, error);
+            return false;
+        }
+    }
+
+    // Lockfile-only backends don't need a table
+    if let Some(table_name) = table_name {
+        match describe_table(&dynamo, table_name).await {
+            Ok(None) => {
+                if !create_table(config, table_name, "LockID").await {
+                    println!("\nFailed to create dynamoDB table");
+                    return false;
+                }
+            }
+            Ok(Some(description)) => {
+                println!("DynamoDB table {} already exists, checking its configuration", table_name.bold());
+                checks.extend(audit_table(table_name, &description, "LockID"));
+            }
+            Err(error) => {
+                println!("Failed to check dynamoDB table {}:\n {}", table_name, error);
+                return false;
+            }
+        }
+    }
+
+    if checks.is_empty() {
+        return true;
+    }
+    println!("{}", drift_table(&checks));
+
+    let drifts: Vec<&Check> = checks.iter().filter(|c| !c.ok).collect();
+    if drifts.is_empty() {
+        println!("State backend configuration is {}", "up to date".green().bold());
+        return true;
+    }
+    let fixable: Vec<&Check> = drifts.iter().filter(|c| c.fix.is_some()).copied().collect();
+    let fix = if fixable.is_empty() {
+        false
+    } else if fix {
+        true
+    } else if is_interactive() {
+        confirm_fix(fixable.len())
+    } else {
+        println!("Run again with --fix to fix the drifted settings");
+        false
     };
 
-    drop(region);
+    let mut fixed = 0;
+    if fix {
+        let s3 = clients.for_bucket(bucket_name).await;
+        for check in &fixable {
+            if apply_fix(&s3, &dynamo, check, settings).await {
+                fixed += 1;
+            }
+        }
+    }
+    if fixed < drifts.len() {
+        println!("\n{} settings of the state backend still drift", drifts.len() - fixed);
+        return false;
+    }
+    println!("State backend configuration {}", "fixed".green().bold());
+    true
+}
+
+pub async fn init_aws_state(matches: &clap::ArgMatches) {
+    let bucket_name_string = arg_or_prompt(matches, "bucket", prompt_bucket_name);
+    let locking = LockingMode::from_matches(matches);
+    let dynamo_string = if locking.uses_table() {
+        Some(arg_or_prompt(matches, "table", prompt_table_name))
+    } else {
+        None
+    };
+    let region_string = resolve_region(matches).await;
 
-    if create_bucket(bucket_name_string.as_str(), region_string).await == false {
-        println!("\nFailed to create bucket, exiting");
-        return;
+    let config = load_config_in_region(matches, Some(region_string.as_str())).await;
+    let settings = StateBucketSettings::from_matches(matches);
+    let fix = matches.get_flag("fix");
+    if !ensure_backend(&config, &bucket_name_string, dynamo_string.as_deref(), &settings, fix).await {
+        std::process::exit(1);
+    }
+    if locking.uses_lockfile() {
+        println!("Set {} in the s3 backend block to lock the states with a lockfile", "use_lockfile = true".bold());
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_ARN: &str = "arn:aws:kms:eu-west-1:111122223333:key/1234abcd";
+
+    fn rule(id: &str, status: ExpirationStatus, days: i32) -> LifecycleRule {
+        LifecycleRule::builder()
+            .id(id)
+            .status(status)
+            .noncurrent_version_expiration(NoncurrentVersionExpiration::builder().noncurrent_days(days).build())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn noncurrent_expiration_of_the_tool_rule() {
+        let rules = [
+            rule("archive", ExpirationStatus::Enabled, 365),
+            rule(NONCURRENT_RULE_ID, ExpirationStatus::Enabled, 90),
+        ];
+        assert_eq!(noncurrent_expiration(&rules), "90 days");
+        assert_eq!(noncurrent_expiration(&rules[..1]), "None");
+        assert_eq!(noncurrent_expiration(&[rule(NONCURRENT_RULE_ID, ExpirationStatus::Disabled, 90)]), "None");
+        assert_eq!(noncurrent_expiration(&[]), "None");
+    }
+
+    #[tokio::test]
+    async fn kms_aliases_match_their_key() {
+        let stub = crate::commands::aws_stub::AwsStub::start(|request| {
+            assert_eq!(request.target(), "TrentService.DescribeKey");
+            let arn = if request.body.contains("alias/state") || request.body.contains("1234abcd") {
+                KEY_ARN
+            } else if request.body.contains("alias/aws/s3") {
+                "arn:aws:kms:eu-west-1:111122223333:key/aws-s3"
+            } else {
+                return (
+                    400,
+                    r#"{"__type": "NotFoundException", "message": "Alias is not found."}"#.to_string(),
+                );
+            };
+            (200, format!(r#"{{"KeyMetadata": {{"KeyId": "{}", "Arn": "{}"}}}}"#, arn.rsplit('/').next().unwrap(), arn))
+        })
+        .await;
+        let kms = aws_sdk_kms::Client::new(&stub.config);
 
-    if create_table(dynamo_string.as_str(), "LockID").await == false {
-        println!("\nFailed to create dynamoDB table, exiting");
-        return;
+        assert!(same_kms_key(&kms, KEY_ARN, "alias/state").await);
+        assert!(same_kms_key(&kms, KEY_ARN, "arn:aws:kms:eu-west-1:111122223333:alias/state").await);
+        assert!(same_kms_key(&kms, "1234abcd", KEY_ARN).await);
+        assert!(same_kms_key(&kms, "", "alias/aws/s3").await);
+        assert!(!same_kms_key(&kms, "", "alias/state").await);
+        assert!(!same_kms_key(&kms, KEY_ARN, "alias/missing").await);
+        // Identical keys don't need KMS
+        let calls = stub.requests().len();
+        assert!(same_kms_key(&kms, "alias/state", "alias/state").await);
+        assert_eq!(stub.requests().len(), calls);
     }
 }
@@ -0,0 +1,297 @@
+use crate::commands::aws_utils::{error_message, load_config};
+use crate::commands::cli_utils::{arg_or_prompt, is_interactive, require_interactive};
+use crate::commands::inti_aws_state::prompt_table_name;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use promkit::preset::confirm::Confirm;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Lock metadata written by terraform in the Info attribute of the lock item
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "PascalCase", default)]
+pub(crate) struct LockInfo {
+    #[serde(rename = "ID")]
+    pub(crate) id: String,
+    pub(crate) operation: String,
+    pub(crate) info: String,
+    pub(crate) who: String,
+    pub(crate) version: String,
+    pub(crate) created: String,
+    pub(crate) path: String,
+}
+
+#[derive(Clone)]
+pub(crate) struct Lock {
+    pub(crate) lock_id: String,
+    // Raw Info attribute, deleting only matches this exact lock
+    raw_info: String,
+    pub(crate) info: LockInfo,
+}
+
+impl Lock {
+    // OperationTypeApply -> apply
+    fn operation(&self) -> String {
+        self.info
+            .operation
+            .trim_start_matches("OperationType")
+            .to_lowercase()
+    }
+
+    fn age(&self) -> String {
+        let Ok(created) = DateTime::from_str(&self.info.created, DateTimeFormat::DateTime) else {
+            return "unknown".to_string();
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let seconds = (now - created.secs()).max(0);
+        match seconds {
+            0..=59 => format!("{}s ago", seconds),
+            60..=3599 => format!("{}m ago", seconds / 60),
+            3600..=86399 => format!("{}h ago", seconds / 3600),
+            _ => format!("{}d ago", seconds / 86400),
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("{} ({} by {}, {})", self.lock_id, self.operation(), self.info.who, self.age())
+    }
+}
+
+// Terraform stores a lock as an item with an Info attribute, state digests (<bucket>/<key>-md5)
+// only have a Digest attribute
+pub(crate) async fn list_locks(client: &aws_sdk_dynamodb::Client, table: &str) -> Result<Vec<Lock>, String> {
+    let mut items = client.scan().table_name(table).into_paginator().items().send();
+    let mut locks = Vec::new();
+    while let Some(item) = items.next().await {
+        let item = item.map_err(|error| error_message(&error))?;
+        let (Some(AttributeValue::S(lock_id)), Some(AttributeValue::S(raw_info))) =
+            (item.get("LockID"), item.get("Info"))
+        else {
+            continue;
+        };
+        locks.push(Lock {
+            lock_id: lock_id.clone(),
+            raw_info: raw_info.clone(),
+            info: serde_json::from_str(raw_info).unwrap_or_default(),
+        });
+    }
+    locks.sort_by(|a, b| a.lock_id.cmp(&b.lock_id));
+    Ok(locks)
+}
+
+// The condition fails when the lock was released (or taken again by another run) in the meantime
+async fn delete_lock(client: &aws_sdk_dynamodb::Client, table: &str, lock: &Lock) -> Result<(), String> {
+    client
+        .delete_item()
+        .table_name(table)
+        .key("LockID", AttributeValue::S(lock.lock_id.clone()))
+        .condition_expression("Info = :info")
+        .expression_attribute_values(":info", AttributeValue::S(lock.raw_info.clone()))
+        .send()
+        .await
+        .map_err(|error| match error.as_service_error() {
+            Some(e) if e.is_conditional_check_failed_exception() => {
+                "the lock changed since it was listed".to_string()
+            }
+            _ => error_message(&error),
+        })?;
+    Ok(())
+}
+
+fn confirm_unlock(lock: &Lock) -> bool {
+    println!("Lock {} held by {} ({}, {})", lock.lock_id.bold(), lock.info.who, lock.operation(), lock.age());
+    println!("Deleting a lock while terraform still runs can corrupt the state");
+    let mut confirm = Confirm::new("Do you want to delete this lock ?").prompt().unwrap();
+    let confirm_string = match confirm.run() {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    confirm_string == "yes" || confirm_string == "y"
+}
+
+fn locks_table(locks: &[Lock]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Lock", "Operation", "Who", "Created", "ID"]);
+    for lock in locks {
+        table.add_row(vec![
+            lock.lock_id.clone(),
+            lock.operation(),
+            lock.info.who.clone(),
+            format!("{} ({})", lock.info.created, lock.age()),
+            lock.info.id.clone(),
+        ]);
+    }
+    table
+}
+
+struct AppState {
+    locks: Vec<Lock>,
+    idx_lock: usize,
+    message: String,
+}
+
+fn clamp_index(idx: usize, len: usize) -> usize {
+    if len == 0 { 0 } else { idx.min(len - 1) }
+}
+
+fn draw_locks(frame: &mut Frame, state: &AppState, table: &str) {
+    use Constraint::{Fill, Length, Min};
+
+    let vertical = Layout::vertical([Min(0), Length(3)]);
+    let [main_area, status_area] = vertical.areas(frame.area());
+    let horizontal = Layout::horizontal([Fill(1); 2]);
+    let [left_area, right_area] = horizontal.areas(main_area);
+
+    // left: locks of the table
+    let items: Vec<ListItem> = state.locks.iter().map(|l| ListItem::new(l.label())).collect();
+    let mut list_state = ListState::default();
+    if !state.locks.is_empty() {
+        list_state.select(Some(clamp_index(state.idx_lock, state.locks.len())));
+    }
+    let list = List::new(items)
+        .block(Block::bordered().title(format!("Locks of {}", table)))
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow));
+    frame.render_stateful_widget(list, left_area, &mut list_state);
+
+    // right: decoded Info of the selected lock
+    let mut details = match state.locks.get(state.idx_lock) {
+        Some(lock) => vec![
+            Line::from(Span::raw(format!("Lock:       {}", lock.lock_id))),
+            Line::from(Span::raw(format!("Path:       {}", lock.info.path))),
+            Line::from(Span::raw(format!("Operation:  {}", lock.operation()))),
+            Line::from(Span::raw(format!("Who:        {}", lock.info.who))),
+            Line::from(Span::raw(format!("Created:    {} ({})", lock.info.created, lock.age()))),
+            Line::from(Span::raw(format!("Version:    {}", lock.info.version))),
+            Line::from(Span::raw(format!("ID:         {}", lock.info.id))),
+            Line::from(Span::raw(format!("Info:       {}", lock.info.info))),
+        ],
+        None => vec![Line::from("No terraform lock in this table")],
+    };
+    details.push(Line::from(""));
+    details.push(Line::from("Use ↑/↓ to move selection, r to refresh, q to quit."));
+    details.push(Line::from("Use d to delete the selected lock (force-unlock)"));
+    let para = Paragraph::new(details).block(Block::bordered().title("Details"));
+    frame.render_widget(para, right_area);
+
+    let status = Paragraph::new(state.message.as_str()).block(Block::bordered());
+    frame.render_widget(status, status_area);
+}
+
+async fn run_locks(
+    terminal: &mut ratatui::DefaultTerminal,
+    client: &aws_sdk_dynamodb::Client,
+    table: &str,
+    locks: Vec<Lock>,
+) -> std::io::Result<()> {
+    let mut state = AppState {
+        message: format!("{} locks", locks.len()),
+        locks,
+        idx_lock: 0,
+    };
+
+    loop {
+        terminal.draw(|frame| draw_locks(frame, &state, table))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let mut reload = false;
+        match key.code {
+            KeyCode::Char('q') => return Ok(()),
+            KeyCode::Up => state.idx_lock = state.idx_lock.saturating_sub(1),
+            KeyCode::Down => state.idx_lock = clamp_index(state.idx_lock + 1, state.locks.len()),
+            KeyCode::Char('r') => reload = true,
+            KeyCode::Char('d') if !state.locks.is_empty() => {
+                let lock = state.locks[state.idx_lock].clone();
+                ratatui::restore();
+                let confirmed = confirm_unlock(&lock);
+                *terminal = ratatui::init();
+                if confirmed {
+                    state.message = match delete_lock(client, table, &lock).await {
+                        Ok(_) => format!("Deleted lock {}", lock.lock_id),
+                        Err(error) => format!("Failed to delete lock {}: {}", lock.lock_id, error),
+                    };
+                    reload = true;
+                }
+            }
+            _ => {}
+        }
+        if reload {
+            match list_locks(client, table).await {
+                Ok(locks) => {
+                    if key.code == KeyCode::Char('r') {
+                        state.message = format!("{} locks", locks.len());
+                    }
+                    state.locks = locks;
+                    state.idx_lock = clamp_index(state.idx_lock, state.locks.len());
+                }
+                Err(error) => state.message = format!("Failed to list the locks: {}", error),
+            }
+        }
+    }
+}
+
+pub async fn locks(matches: &clap::ArgMatches) {
+    let table = arg_or_prompt(matches, "table", prompt_table_name);
+    let config = load_config(matches).await;
+    let client = aws_sdk_dynamodb::Client::new(&config);
+
+    let locks = match list_locks(&client, &table).await {
+        Ok(locks) => locks,
+        Err(error) => {
+            println!("Failed to list the locks of {}:\n {}", table, error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(lock_id) = matches.get_one::<String>("delete") {
+        let Some(lock) = locks.iter().find(|l| &l.lock_id == lock_id || &l.info.id == lock_id) else {
+            println!("No lock {} in {}", lock_id, table);
+            std::process::exit(1);
+        };
+        if !matches.get_flag("yes") {
+            require_interactive("yes");
+            if !confirm_unlock(lock) {
+                println!("Aborted by user");
+                std::process::exit(1);
+            }
+        }
+        if let Err(error) = delete_lock(&client, &table, lock).await {
+            println!("Failed to delete lock {}:\n {}", lock.lock_id, error);
+            std::process::exit(1);
+        }
+        println!("Lock {} deleted {}", lock.lock_id, "successfully".green().bold());
+        return;
+    }
+
+    if !is_interactive() {
+        println!("{}", locks_table(&locks));
+        return;
+    }
+    let mut terminal = ratatui::init();
+    let result = run_locks(&mut terminal, &client, &table, locks).await;
+    ratatui::restore();
+    result.expect("Can't display the locks");
+}
@@ -1,9 +1,20 @@
+#[cfg(test)]
+mod aws_stub;
 mod aws_utils;
 mod cli_utils;
+mod config;
 pub mod delete_bucket;
 pub mod ecs_connect;
 pub mod ec2_connect;
+pub mod forward;
+mod forward_daemon;
 pub mod init;
 pub mod inti_aws_state;
+pub mod locks;
 pub mod module;
 pub mod port_forward;
+pub mod profile;
+mod ssm_session;
+pub mod state;
+pub mod state_backend;
+pub mod state_history;
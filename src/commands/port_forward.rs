@@ -1,37 +1,134 @@
-use crate::commands::aws_utils::list_ec2_instances;
+use crate::commands::aws_utils::{
+    find_ec2_instance, get_clusters, list_cluster_services, list_database_endpoints, list_ec2_instances,
+    list_service_tasks, list_task_container, load_config, run_aws_cli_with, use_aws_cli, DatabaseEndpoint,
+};
+use crate::commands::forward::{self, ForwardSpec};
+use crate::commands::ssm_session;
+use crate::commands::cli_utils::{get_index_of, is_interactive, require_interactive, select_type};
 use aws_sdk_ec2 as ec2;
+use aws_sdk_ecs as ecs;
+use promkit::preset::confirm::Confirm;
 use promkit::preset::listbox::Listbox;
 use promkit::preset::readline::Readline;
+use serde_json::json;
 
-pub(crate) async fn connect_to_ecs_command(target: &str, host: &str, local_port: &str, remote_port: &str) {
-    ctrlc::set_handler(move || {}).expect("Error setting Ctrl-C handler");
+#[derive(Clone, Copy)]
+pub enum LocalPort {
+    Auto,
+    Port(u16),
+}
 
-    let document = "AWS-StartPortForwardingSessionToRemoteHost";
-    let params = format!(
-        "\'{{\"portNumber\":[\"{}\"],\"localPortNumber\":[\"{}\"], \"host\":[\"{}\"]}}\'",
-        remote_port, local_port, host
-    );
-    let command = format!(
-        "aws ssm start-session --target {} --document-name {} --parameters {}",
-        target, document, params
-    );
+// Value of --local-port: a port number or `auto`
+pub fn parse_local_port(value: &str) -> Result<LocalPort, String> {
+    if value == "auto" {
+        return Ok(LocalPort::Auto);
+    }
+    parse_port(value).map(LocalPort::Port)
+}
 
-    println!("{}", command);
+pub(crate) fn parse_port(value: &str) -> Result<u16, String> {
+    match value.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!("{} is not a port number (1-65535)", value)),
+    }
+}
 
-    let output = std::process::Command::new("/bin/sh")
-        .arg("-c")
-        .arg(command)
-        .spawn()
-        .expect("failed to execute process");
-    let _ = output.wait_with_output();
+fn bind_local(port: u16) -> Option<std::net::TcpListener> {
+    std::net::TcpListener::bind(("127.0.0.1", port)).ok()
 }
 
-pub(crate) fn select_port(question: &String) -> String {
+fn confirm_port(question: &str) -> bool {
+    let mut confirm = Confirm::new(question).prompt().unwrap();
+    let confirm_string = match confirm.run() {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    confirm_string == "yes" || confirm_string == "y"
+}
+
+// `auto` takes any free port, a busy port is replaced by the next free one once the user agrees.
+// The port is held until the forward takes it over, so that nothing else grabs it meanwhile
+pub(crate) fn resolve_local_port(local_port: LocalPort) -> std::net::TcpListener {
+    let port = match local_port {
+        LocalPort::Port(port) => port,
+        LocalPort::Auto => match std::net::TcpListener::bind(("127.0.0.1", 0)) {
+            Ok(listener) => return listener,
+            Err(error) => {
+                println!("Can't find a free local port: {}", error);
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Some(listener) = bind_local(port) {
+        return listener;
+    }
+    let Some((free, listener)) =
+        (port.saturating_add(1)..=u16::MAX).take(100).find_map(|p| bind_local(p).map(|l| (p, l)))
+    else {
+        println!("localhost:{} is already in use, use --local-port auto", port);
+        std::process::exit(1);
+    };
+    if !is_interactive() {
+        println!("localhost:{} is already in use, use --local-port {} or --local-port auto", port, free);
+        std::process::exit(1);
+    }
+    if !confirm_port(&format!("localhost:{} is already in use, do you want to use {} instead ?", port, free)) {
+        println!("Aborted by user");
+        std::process::exit(1);
+    }
+    listener
+}
+
+pub(crate) fn listener_port(listener: &std::net::TcpListener) -> u16 {
+    listener.local_addr().map(|address| address.port()).unwrap_or_default()
+}
+
+pub(crate) async fn connect_to_ecs_command(
+    matches: &clap::ArgMatches,
+    target: &str,
+    host: &str,
+    local_port: LocalPort,
+    remote_port: u16,
+) {
+    let listener = resolve_local_port(local_port);
+    if !use_aws_cli(matches) {
+        let config = load_config(matches).await;
+        ssm_session::exit_with_port_forward(&config, target, host, listener, remote_port).await;
+    }
+    // The plugin listens on the port itself and says when it waits for connections
+    let local_port = listener_port(&listener);
+    drop(listener);
+    run_aws_cli_with(matches, port_forward_args(target, host, local_port, remote_port));
+}
+
+// Arguments of `aws ssm start-session` forwarding localhost:<local_port> to host:remote_port
+pub(crate) fn port_forward_args(target: &str, host: &str, local_port: u16, remote_port: u16) -> Vec<String> {
+    let parameters = json!({
+        "portNumber": [remote_port.to_string()],
+        "localPortNumber": [local_port.to_string()],
+        "host": [host],
+    });
+    vec![
+        "ssm".into(),
+        "start-session".into(),
+        "--target".into(),
+        target.into(),
+        "--document-name".into(),
+        "AWS-StartPortForwardingSessionToRemoteHost".into(),
+        "--parameters".into(),
+        parameters.to_string(),
+    ]
+}
+
+pub(crate) fn select_port(question: &str) -> u16 {
     let mut port = Readline::default()
         .title(question)
         .validator(
-            |text| text.parse::<f64>().is_ok(),
-            |text| format!("Your port should be a number {}", text),
+            |text| parse_port(text).is_ok(),
+            |text| format!("Your port should be a number between 1 and 65535 {}", text),
         )
         .prompt()
         .unwrap();
@@ -44,14 +141,34 @@ pub(crate) fn select_port(question: &String) -> String {
         }
     };
     drop(port);
-    port_string
+    parse_port(&port_string).unwrap()
 }
 
-pub(crate) fn select_host(question: &String) -> String {
+pub(crate) fn select_local_port(question: &str) -> LocalPort {
+    let mut port = Readline::default()
+        .title(format!("{} (auto for any free port)", question))
+        .validator(
+            |text| parse_local_port(text).is_ok(),
+            |text| format!("Your port should be auto or a number between 1 and 65535 {}", text),
+        )
+        .prompt()
+        .unwrap();
+    let port_string = match port.run() {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    drop(port);
+    parse_local_port(&port_string).unwrap()
+}
+
+pub(crate) fn select_host(question: &str) -> String {
     let mut host = Readline::default()
         .title(question)
         .validator(
-            |text| text.len() > 0,
+            |text| !text.is_empty(),
             |text| format!("Your host can't be empty {}", text.len()),
         )
         .prompt()
@@ -67,41 +184,337 @@ pub(crate) fn select_host(question: &String) -> String {
     host_string
 }
 
-async fn connect_to_ec2_instance() {
-    let config = aws_config::load_from_env().await;
-    let client = ec2::Client::new(&config);
-
-    let instances = list_ec2_instances(&client).await;
-    if instances.is_empty() {
-        println!("No instances found");
-        return;
+// Host and port of a discovered database, or a host typed by the user (without port)
+pub(crate) async fn select_endpoint(matches: &clap::ArgMatches) -> (String, Option<u16>) {
+    let config = load_config(matches).await;
+    let endpoints = list_database_endpoints(&config).await;
+    if endpoints.is_empty() {
+        return (select_host("What host do you want to use?"), None);
+    }
+    let other = "Other host".to_string();
+    let mut choices: Vec<String> = endpoints.iter().map(|e| format!("{} - {}:{}", e.name, e.host, e.port)).collect();
+    choices.push(other.clone());
+    let choice = Listbox::new(&choices)
+        .title("Which endpoint do you want to forward?")
+        .listbox_lines(10)
+        .prompt()
+        .unwrap()
+        .run();
+    let choice = match choice {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    if choice == other {
+        return (select_host("What host do you want to use?"), None);
     }
-    let instances_id: Vec<String> = instances.iter().map(|i| i.instance_id.clone()).collect();
-    let instances_name: Vec<String> = instances.iter().map(|i| i.name.clone()).collect();
-    let instance = Listbox::new(&instances_name)
+    let endpoint = &endpoints[get_index_of(&choices, choice)];
+    (endpoint.host.clone(), Some(endpoint.port))
+}
+
+// Value of --endpoint: an RDS instance/cluster or ElastiCache replication group ID, optionally
+// with the role of the endpoint (e.g. my-cluster:reader), or its host
+fn matching_endpoints(endpoints: Vec<DatabaseEndpoint>, name: &str) -> Vec<DatabaseEndpoint> {
+    let (id, role) = match name.split_once(':') {
+        Some((id, role)) => (id, Some(role)),
+        None => (name, None),
+    };
+    endpoints
+        .into_iter()
+        .filter(|e| e.host == name || (e.id == id && role.is_none_or(|role| e.role == role)))
+        .collect()
+}
+
+async fn find_endpoint(matches: &clap::ArgMatches, name: &str) -> (String, u16) {
+    let config = load_config(matches).await;
+    let endpoints = matching_endpoints(list_database_endpoints(&config).await, name);
+    let endpoint = match endpoints.as_slice() {
+        [] => {
+            println!("No RDS or ElastiCache endpoint found for {}", name);
+            std::process::exit(1);
+        }
+        [endpoint] => endpoint,
+        _ if !is_interactive() => {
+            let choices: Vec<String> = endpoints.iter().map(|e| format!("{}:{} ({})", e.id, e.role, e.host)).collect();
+            println!("{} matches several endpoints, use one of {}", name, choices.join(", "));
+            std::process::exit(1);
+        }
+        _ => {
+            let choices: Vec<String> = endpoints.iter().map(|e| format!("{} - {}:{}", e.name, e.host, e.port)).collect();
+            let choice = Listbox::new(&choices)
+                .title("Which endpoint do you want to forward?")
+                .listbox_lines(10)
+                .prompt()
+                .unwrap()
+                .run();
+            match choice {
+                Ok(choice) => &endpoints[get_index_of(&choices, choice)],
+                Err(_) => {
+                    print!("Aborted by user");
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    (endpoint.host.clone(), endpoint.port)
+}
+
+fn prompt_instance(instances_name: &[String]) -> String {
+    Listbox::new(instances_name)
         .title("Which instance do you want?")
         .listbox_lines(5)
         .prompt()
         .unwrap()
         .run()
-        .unwrap();
-    let target = &instances_id[crate::commands::cli_utils::get_index_of(&instances_name, instance)];
+        .unwrap()
+}
 
-    let host = select_host(&"What host do you want to use?".to_string());
-    let remote_port = select_port(&"What remote port do you want to use?".to_string());
-    let local_port = select_port(&"What local port do you want to use?".to_string());
+async fn connect_to_ec2_instance(matches: &clap::ArgMatches) {
+    let config = load_config(matches).await;
+    let client = ec2::Client::new(&config);
+
+    let target = match matches.get_one::<String>("instance") {
+        Some(instance) => match find_ec2_instance(&client, instance).await {
+            Some(target) => target,
+            None => {
+                println!("No running instance found for {}", instance);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            require_interactive("instance");
+            let instances = list_ec2_instances(&client).await;
+            if instances.is_empty() {
+                println!("No instances found");
+                return;
+            }
+            let instances_id: Vec<String> = instances.iter().map(|i| i.instance_id.clone()).collect();
+            let instances_name: Vec<String> = instances.iter().map(|i| i.name.clone()).collect();
+            let instance = prompt_instance(&instances_name);
+            instances_id[get_index_of(&instances_name, instance)].clone()
+        }
+    };
 
-    connect_to_ecs_command(&target, &host, &local_port, &remote_port).await;
+    // --keep-alive looks the instance up by name again
+    let spec_target = matches.get_one::<String>("instance").unwrap_or(&target).clone();
+    forward_to(matches, &target, &spec_target).await;
 }
 
-pub async fn port_forward() {
-    let selected_type = crate::commands::cli_utils::select_type();
+// `spec_target` is `target` in the format of aws_utils::resolve_target, to find it again after a drop
+async fn forward_to(matches: &clap::ArgMatches, target: &str, spec_target: &str) {
+    let (host, endpoint_port) = match (matches.get_one::<String>("host"), matches.get_one::<String>("endpoint")) {
+        (Some(host), _) => (host.clone(), None),
+        (None, Some(name)) => {
+            let (host, port) = find_endpoint(matches, name).await;
+            (host, Some(port))
+        }
+        (None, None) => {
+            require_interactive("host");
+            select_endpoint(matches).await
+        }
+    };
+    let remote_port = match matches.get_one::<u16>("remote-port").copied().or(endpoint_port) {
+        Some(port) => port,
+        None => {
+            require_interactive("remote-port");
+            select_port("What remote port do you want to use?")
+        }
+    };
+    let local_port = match matches.get_one::<LocalPort>("local-port") {
+        Some(port) => *port,
+        None => {
+            require_interactive("local-port");
+            select_local_port("What local port do you want to use?")
+        }
+    };
+
+    if matches.get_flag("keep-alive") {
+        let listener = resolve_local_port(local_port);
+        let spec = ForwardSpec {
+            target: spec_target.to_string(),
+            host,
+            remote_port,
+            local_port: listener_port(&listener),
+        };
+        forward::keep_alive(matches, spec, Some(listener)).await;
+        return;
+    }
+    connect_to_ecs_command(matches, target, &host, local_port, remote_port).await;
+}
+
+// Value of the flag, the only choice, or the one picked in a prompt
+fn select_resource(matches: &clap::ArgMatches, flag: &str, question: &str, names: &[String]) -> String {
+    if let Some(value) = matches.get_one::<String>(flag) {
+        return value.clone();
+    }
+    match names {
+        [] => {
+            println!("No {} found", flag);
+            std::process::exit(1);
+        }
+        [name] => name.clone(),
+        _ => {
+            require_interactive(flag);
+            Listbox::new(names)
+                .title(question)
+                .listbox_lines(5)
+                .prompt()
+                .unwrap()
+                .run()
+                .unwrap()
+        }
+    }
+}
+
+async fn connect_to_ecs_container(matches: &clap::ArgMatches) {
+    let config = load_config(matches).await;
+    let client = ecs::Client::new(&config);
+
+    let cluster = match matches.get_one::<String>("cluster") {
+        Some(cluster) => cluster.clone(),
+        None => {
+            let clusters: Vec<String> = get_clusters(&client).await.into_iter().map(|c| c.name).collect();
+            select_resource(matches, "cluster", "Which cluster do you want?", &clusters)
+        }
+    };
+
+    let (task, service) = match matches.get_one::<String>("task") {
+        Some(task) => (task.clone(), None),
+        None => {
+            let service = match matches.get_one::<String>("service") {
+                Some(service) => service.clone(),
+                None => {
+                    let services: Vec<String> =
+                        list_cluster_services(&client, &cluster).await.into_iter().map(|s| s.name).collect();
+                    select_resource(matches, "service", "Which service do you want?", &services)
+                }
+            };
+            let tasks: Vec<String> =
+                list_service_tasks(&client, &cluster, &service).await.into_iter().map(|t| t.name).collect();
+            if tasks.is_empty() {
+                println!("No running task found for service {}", service);
+                std::process::exit(1);
+            }
+            // Any task of the service will do when we can't ask
+            let task = if !is_interactive() {
+                tasks[0].clone()
+            } else {
+                select_resource(matches, "task", "Which task do you want?", &tasks)
+            };
+            (task, Some(service))
+        }
+    };
+
+    let containers = list_task_container(&client, &cluster, &task).await;
+    let names: Vec<String> = containers.iter().map(|c| c.name.clone()).collect();
+    let container = select_resource(matches, "container", "Which container do you want?", &names);
+    let Some(container) = containers.iter().find(|c| c.name == container) else {
+        println!("No running container {} found in task {}", container, task);
+        std::process::exit(1);
+    };
+
+    let target = format!("ecs:{}_{}_{}", cluster, task, container.runtime_id);
+    // --keep-alive picks another task of the service when the task was chosen through it
+    let spec_target = match service {
+        Some(service) => format!("ecs-service:{}/{}/{}", cluster, service, container.name),
+        None => format!("ecs-task:{}/{}/{}", cluster, task, container.name),
+    };
+    forward_to(matches, &target, &spec_target).await;
+}
+
+pub async fn port_forward(matches: &clap::ArgMatches) {
+    let selected_type = match matches.get_one::<String>("type") {
+        Some(selected_type) => selected_type.clone(),
+        None => {
+            require_interactive("type");
+            select_type()
+        }
+    };
     match selected_type.as_str() {
-        "EC2" => {
-            connect_to_ec2_instance().await;
+        "EC2" | "ec2" => {
+            connect_to_ec2_instance(matches).await;
+        }
+        "ECS container" | "ecs" => {
+            connect_to_ecs_container(matches).await;
         }
         _ => {
             println!("Invalid selection");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_local_ports() {
+        assert!(matches!(parse_local_port("auto"), Ok(LocalPort::Auto)));
+        assert!(matches!(parse_local_port("5432"), Ok(LocalPort::Port(5432))));
+        assert!(matches!(parse_local_port("65535"), Ok(LocalPort::Port(65535))));
+        for value in ["", "0", "65536", "-1", "Auto", "54 32", "db"] {
+            assert!(parse_local_port(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn resolved_ports_stay_taken() {
+        let listener = resolve_local_port(LocalPort::Auto);
+        let port = listener_port(&listener);
+        assert_ne!(port, 0);
+        assert!(bind_local(port).is_none());
+        drop(listener);
+
+        let listener = resolve_local_port(LocalPort::Port(port));
+        assert_eq!(listener_port(&listener), port);
+        assert!(bind_local(port).is_none());
+    }
+
+    fn endpoint(id: &str, role: &str, host: &str) -> DatabaseEndpoint {
+        DatabaseEndpoint {
+            id: id.to_string(),
+            role: role.to_string(),
+            name: format!("{} {}", id, role),
+            host: host.to_string(),
+            port: 5432,
+        }
+    }
+
+    fn endpoints() -> Vec<DatabaseEndpoint> {
+        vec![
+            endpoint("prod", "writer", "prod.cluster-abc.rds.amazonaws.com"),
+            endpoint("prod", "reader", "prod.cluster-ro-abc.rds.amazonaws.com"),
+            endpoint("prod-replica", "instance", "prod-replica.abc.rds.amazonaws.com"),
+            endpoint("sessions", "primary", "sessions.abc.cache.amazonaws.com"),
+        ]
+    }
+
+    fn hosts(name: &str) -> Vec<String> {
+        matching_endpoints(endpoints(), name).into_iter().map(|e| e.host).collect()
+    }
+
+    #[test]
+    fn match_endpoint_by_id_and_role() {
+        assert_eq!(hosts("prod:reader"), vec!["prod.cluster-ro-abc.rds.amazonaws.com"]);
+        assert_eq!(hosts("prod:writer"), vec!["prod.cluster-abc.rds.amazonaws.com"]);
+        assert_eq!(hosts("prod-replica"), vec!["prod-replica.abc.rds.amazonaws.com"]);
+        assert_eq!(hosts("sessions:primary"), vec!["sessions.abc.cache.amazonaws.com"]);
+    }
+
+    #[test]
+    fn match_endpoint_by_host() {
+        assert_eq!(hosts("prod.cluster-ro-abc.rds.amazonaws.com"), vec!["prod.cluster-ro-abc.rds.amazonaws.com"]);
+    }
+
+    #[test]
+    fn match_endpoint_ambiguous_or_missing() {
+        // The writer and the reader of the cluster share its ID, no prefix matching
+        assert_eq!(hosts("prod").len(), 2);
+        assert!(hosts("prod:custom").is_empty());
+        assert!(hosts("pro").is_empty());
+        assert!(hosts("sessions:writer").is_empty());
+        assert!(hosts("").is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+use crate::commands::aws_utils::{ecs_execute_command, load_config, resolve_target, Target};
+use crate::commands::config::{config_path, read_config, Profile};
+use crate::commands::ec2_connect::connect_to_ec2_command;
+use crate::commands::forward::{keep_alive, ForwardSpec};
+use crate::commands::port_forward::{connect_to_ecs_command, LocalPort};
+
+pub(crate) fn find_profile(name: &str) -> Profile {
+    let profiles = read_config().profiles;
+    if let Some(profile) = profiles.get(name) {
+        return profile.clone();
+    }
+    println!("No profile {} in {}", name, config_path().display());
+    if !profiles.is_empty() {
+        let names: Vec<&str> = profiles.keys().map(|k| k.as_str()).collect();
+        println!("Available profiles: {}", names.join(", "));
+    }
+    std::process::exit(1);
+}
+
+pub(crate) fn profile_forward_spec(name: &str) -> ForwardSpec {
+    match find_profile(name).forward_spec() {
+        Ok(spec) => spec,
+        Err(error) => {
+            println!("Invalid profile {}: {}", name, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Port forward when the profile has a remote port, a shell otherwise
+pub async fn connect(matches: &clap::ArgMatches) {
+    let name = matches.get_one::<String>("name").unwrap();
+    let profile = find_profile(name);
+    let config = load_config(matches).await;
+
+    if profile.remote_port.is_some() {
+        let spec = profile_forward_spec(name);
+        if matches.get_flag("keep-alive") {
+            keep_alive(matches, spec, None).await;
+            return;
+        }
+        let target = match resolve_target(&config, &spec.target).await {
+            Ok(target) => target.ssm_target(),
+            Err(error) => {
+                println!("Can't find the target of {}: {}", name, error);
+                std::process::exit(1);
+            }
+        };
+        connect_to_ecs_command(matches, &target, &spec.host, LocalPort::Port(spec.local_port), spec.remote_port).await;
+        return;
+    }
+
+    let target = match profile.target() {
+        Ok(target) => target,
+        Err(error) => {
+            println!("Invalid profile {}: {}", name, error);
+            std::process::exit(1);
+        }
+    };
+    match resolve_target(&config, &target).await {
+        Ok(Target::Ssm(target)) => connect_to_ec2_command(matches, &target).await,
+        Ok(Target::Container { cluster, task, container, .. }) => {
+            let command = profile.command.as_deref().unwrap_or("/bin/sh");
+            ecs_execute_command(matches, &cluster, &task, &container, command).await;
+        }
+        Err(error) => {
+            println!("Can't find the target of {}: {}", name, error);
+            std::process::exit(1);
+        }
+    }
+}
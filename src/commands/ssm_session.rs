@@ -0,0 +1,1121 @@
+// Session Manager data channel, what the session-manager-plugin does for the aws CLI: a websocket
+// carrying binary client messages (header + SHA-256 digest + payload) that are sequenced, acked
+// and resent, a handshake with the agent, then either a shell or TCP port forwards (multiplexed
+// with smux when the agent supports it)
+use crate::commands::aws_utils::error_message;
+use aws_config::SdkConfig;
+use colored::Colorize;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+const CLIENT_VERSION: &str = "1.2.0.0";
+// Same chunk size as the session-manager-plugin
+const STREAM_DATA_PAYLOAD_SIZE: usize = 1024;
+const HEADER_LENGTH: usize = 116;
+const MESSAGE_TYPE_LENGTH: usize = 32;
+const RESEND_TIMEOUT: Duration = Duration::from_secs(2);
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+const SMUX_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// First agent version multiplexing port forwards with smux
+const MULTIPLEXING_AGENT_VERSION: [u32; 4] = [3, 0, 196, 0];
+
+const INPUT_STREAM_DATA: &str = "input_stream_data";
+const OUTPUT_STREAM_DATA: &str = "output_stream_data";
+const ACKNOWLEDGE: &str = "acknowledge";
+const CHANNEL_CLOSED: &str = "channel_closed";
+const START_PUBLICATION: &str = "start_publication";
+const PAUSE_PUBLICATION: &str = "pause_publication";
+
+const FLAG_DATA: u64 = 0;
+const FLAG_ACK: u64 = 3;
+
+pub(crate) const PAYLOAD_OUTPUT: u32 = 1;
+const PAYLOAD_SIZE: u32 = 3;
+const PAYLOAD_HANDSHAKE_REQUEST: u32 = 5;
+const PAYLOAD_HANDSHAKE_RESPONSE: u32 = 6;
+const PAYLOAD_HANDSHAKE_COMPLETE: u32 = 7;
+const PAYLOAD_ENC_CHALLENGE_REQUEST: u32 = 8;
+const PAYLOAD_FLAG: u32 = 10;
+const PAYLOAD_STDERR: u32 = 11;
+const PAYLOAD_EXIT_CODE: u32 = 12;
+
+// Payloads of PAYLOAD_FLAG messages
+const FLAG_DISCONNECT_TO_PORT: u32 = 1;
+
+const ACTION_SUCCESS: u32 = 1;
+const ACTION_UNSUPPORTED: u32 = 3;
+
+const SMUX_VERSION: u8 = 1;
+const SMUX_SYN: u8 = 0;
+const SMUX_FIN: u8 = 1;
+const SMUX_PSH: u8 = 2;
+const SMUX_NOP: u8 = 3;
+const SMUX_HEADER_LENGTH: usize = 8;
+
+// What StartSession (or ECS ExecuteCommand) returns
+pub(crate) struct Session {
+    pub(crate) session_id: String,
+    pub(crate) stream_url: String,
+    pub(crate) token_value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ClientMessage {
+    pub(crate) message_type: String,
+    pub(crate) schema_version: u32,
+    pub(crate) created_date: u64,
+    pub(crate) sequence_number: i64,
+    pub(crate) flags: u64,
+    pub(crate) message_id: Uuid,
+    pub(crate) payload_type: u32,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl ClientMessage {
+    pub(crate) fn new(message_type: &str, sequence_number: i64, flags: u64, payload_type: u32, payload: Vec<u8>) -> Self {
+        Self {
+            message_type: message_type.to_string(),
+            schema_version: 1,
+            created_date: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+            sequence_number,
+            flags,
+            message_id: Uuid::new_v4(),
+            payload_type,
+            payload,
+        }
+    }
+
+    // The UUID is stored least significant half first, like the Java/Go implementations
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + 4 + self.payload.len());
+        bytes.extend_from_slice(&(HEADER_LENGTH as u32).to_be_bytes());
+        let mut message_type = self.message_type.clone().into_bytes();
+        message_type.resize(MESSAGE_TYPE_LENGTH, b' ');
+        bytes.extend_from_slice(&message_type);
+        bytes.extend_from_slice(&self.schema_version.to_be_bytes());
+        bytes.extend_from_slice(&self.created_date.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence_number.to_be_bytes());
+        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        let id = self.message_id.as_bytes();
+        bytes.extend_from_slice(&id[8..]);
+        bytes.extend_from_slice(&id[..8]);
+        bytes.extend_from_slice(&Sha256::digest(&self.payload));
+        bytes.extend_from_slice(&self.payload_type.to_be_bytes());
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    pub(crate) fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LENGTH + 4 {
+            return Err(format!("message too short ({} bytes)", bytes.len()));
+        }
+        let u32_at = |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let u64_at = |offset: usize| u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let header_length = u32_at(0) as usize;
+        if header_length < HEADER_LENGTH || bytes.len() < header_length + 4 {
+            return Err(format!("invalid header length {}", header_length));
+        }
+        let payload_length = u32_at(header_length) as usize;
+        let payload_start = header_length + 4;
+        if bytes.len() < payload_start + payload_length {
+            return Err(format!("truncated payload ({} of {} bytes)", bytes.len() - payload_start, payload_length));
+        }
+        let payload = bytes[payload_start..payload_start + payload_length].to_vec();
+        if payload_length > 0 && Sha256::digest(&payload).as_slice() != &bytes[80..112] {
+            return Err("payload digest mismatch".to_string());
+        }
+        let mut id = [0u8; 16];
+        id[..8].copy_from_slice(&bytes[72..80]);
+        id[8..].copy_from_slice(&bytes[64..72]);
+        Ok(Self {
+            message_type: String::from_utf8_lossy(&bytes[4..4 + MESSAGE_TYPE_LENGTH])
+                .trim_end_matches([' ', '\0'])
+                .to_string(),
+            schema_version: u32_at(36),
+            created_date: u64_at(40),
+            sequence_number: u64_at(48) as i64,
+            flags: u64_at(56),
+            message_id: Uuid::from_bytes(id),
+            payload_type: u32_at(112),
+            payload,
+        })
+    }
+}
+
+// Session type and properties the agent asked for, e.g. Port with {"Type": "LocalPortForwarding"}
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Handshake {
+    pub(crate) agent_version: String,
+    pub(crate) session_type: String,
+    pub(crate) properties: Value,
+}
+
+impl Handshake {
+    fn multiplexing(&self) -> bool {
+        let version: Vec<u32> = self.agent_version.split('.').map(|v| v.parse().unwrap_or(0)).collect();
+        self.properties.get("Type").and_then(|t| t.as_str()) == Some("LocalPortForwarding")
+            && version.as_slice() >= MULTIPLEXING_AGENT_VERSION.as_slice()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ChannelEvent {
+    Ready(Handshake),
+    Data(u32, Vec<u8>),
+    Closed(Option<String>),
+}
+
+// Handle on the websocket task: input goes out in sequence, output comes back in order
+pub(crate) struct DataChannel {
+    input: mpsc::UnboundedSender<(u32, Vec<u8>)>,
+    events: mpsc::UnboundedReceiver<ChannelEvent>,
+}
+
+impl DataChannel {
+    // Works with the wss:// stream URL of a session as well as a local ws:// stand-in
+    pub(crate) async fn open(session: &Session) -> Result<Self, String> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(session.stream_url.as_str())
+            .await
+            .map_err(|error| format!("can't open the data channel: {}", error))?;
+        let open = json!({
+            "MessageSchemaVersion": "1.0",
+            "RequestId": Uuid::new_v4().to_string(),
+            "TokenValue": session.token_value,
+            "ClientId": Uuid::new_v4().to_string(),
+            "ClientVersion": CLIENT_VERSION,
+        });
+        socket
+            .send(Message::Text(open.to_string()))
+            .await
+            .map_err(|error| format!("can't open the data channel: {}", error))?;
+
+        let (input, input_rx) = mpsc::unbounded_channel();
+        let (events_tx, events) = mpsc::unbounded_channel();
+        tokio::spawn(run_channel(socket, input_rx, events_tx));
+        Ok(Self { input, events })
+    }
+
+    pub(crate) fn send(&self, payload_type: u32, payload: &[u8]) {
+        for chunk in payload.chunks(STREAM_DATA_PAYLOAD_SIZE) {
+            let _ = self.input.send((payload_type, chunk.to_vec()));
+        }
+    }
+
+    pub(crate) async fn recv(&mut self) -> ChannelEvent {
+        self.events.recv().await.unwrap_or(ChannelEvent::Closed(None))
+    }
+
+    // Output received before the handshake completes (shell banners) is handed back
+    pub(crate) async fn wait_ready(&mut self) -> Result<(Handshake, Vec<(u32, Vec<u8>)>), String> {
+        let mut early = Vec::new();
+        loop {
+            match self.recv().await {
+                ChannelEvent::Ready(handshake) => return Ok((handshake, early)),
+                ChannelEvent::Data(payload_type, payload) => early.push((payload_type, payload)),
+                ChannelEvent::Closed(reason) => {
+                    return Err(reason.unwrap_or_else(|| "the session was closed during the handshake".to_string()))
+                }
+            }
+        }
+    }
+}
+
+fn handshake_response(request: &[u8], handshake: &mut Handshake) -> Result<Vec<u8>, String> {
+    let request: Value = serde_json::from_slice(request).map_err(|error| format!("invalid handshake: {}", error))?;
+    handshake.agent_version = request["AgentVersion"].as_str().unwrap_or_default().to_string();
+    let mut processed = Vec::new();
+    let mut errors = Vec::new();
+    for action in request["RequestedClientActions"].as_array().into_iter().flatten() {
+        let action_type = action["ActionType"].as_str().unwrap_or_default();
+        let status = match action_type {
+            "SessionType" => {
+                handshake.session_type = action["ActionParameters"]["SessionType"].as_str().unwrap_or_default().to_string();
+                handshake.properties = action["ActionParameters"]["Properties"].clone();
+                ACTION_SUCCESS
+            }
+            _ => {
+                errors.push(format!("{} is not supported, use --aws-cli", action_type));
+                ACTION_UNSUPPORTED
+            }
+        };
+        processed.push(json!({
+            "ActionType": action_type,
+            "ActionStatus": status,
+            "ActionResult": Value::Null,
+            "Error": if status == ACTION_SUCCESS { String::new() } else { format!("{} is not supported", action_type) },
+        }));
+    }
+    let response = json!({
+        "ClientVersion": CLIENT_VERSION,
+        "ProcessedClientActions": processed,
+        "Errors": errors,
+    });
+    if !errors.is_empty() {
+        return Err(errors.join(", "));
+    }
+    Ok(response.to_string().into_bytes())
+}
+
+fn acknowledge(message: &ClientMessage) -> ClientMessage {
+    let payload = json!({
+        "AcknowledgedMessageType": message.message_type,
+        "AcknowledgedMessageId": message.message_id.to_string(),
+        "AcknowledgedMessageSequenceNumber": message.sequence_number,
+        "IsSequentialMessage": true,
+    });
+    ClientMessage::new(ACKNOWLEDGE, 0, FLAG_ACK, 0, payload.to_string().into_bytes())
+}
+
+async fn run_channel<S>(
+    socket: S,
+    mut input: mpsc::UnboundedReceiver<(u32, Vec<u8>)>,
+    events: mpsc::UnboundedSender<ChannelEvent>,
+) where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+        + futures_util::Sink<Message, Error = tokio_tungstenite::tungstenite::Error>
+        + Unpin,
+{
+    let (mut sink, mut stream) = socket.split();
+    let mut next_sequence: i64 = 0;
+    let mut expected_sequence: i64 = 0;
+    // Input not acked yet, resent after RESEND_TIMEOUT
+    let mut unacked: BTreeMap<i64, (Vec<u8>, Instant)> = BTreeMap::new();
+    // Output received ahead of a missing message
+    let mut out_of_order: BTreeMap<i64, ClientMessage> = BTreeMap::new();
+    // Input held while the agent paused the publication
+    let mut paused = false;
+    let mut pending: VecDeque<(u32, Vec<u8>)> = VecDeque::new();
+    let mut handshake = Handshake::default();
+    let mut tick = tokio::time::interval(Duration::from_millis(500));
+    let mut last_ping = Instant::now();
+
+    macro_rules! send {
+        ($bytes:expr) => {
+            if sink.send(Message::Binary($bytes)).await.is_err() {
+                let _ = events.send(ChannelEvent::Closed(Some("the data channel was closed".to_string())));
+                return;
+            }
+        };
+    }
+
+    loop {
+        tokio::select! {
+            frame = stream.next() => {
+                let bytes = match frame {
+                    Some(Ok(Message::Binary(bytes))) => bytes,
+                    Some(Ok(Message::Close(_))) | None => {
+                        let _ = events.send(ChannelEvent::Closed(None));
+                        return;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(error)) => {
+                        let _ = events.send(ChannelEvent::Closed(Some(error.to_string())));
+                        return;
+                    }
+                };
+                let message = match ClientMessage::deserialize(&bytes) {
+                    Ok(message) => message,
+                    // A corrupted message isn't acked, the agent sends it again
+                    Err(_) => continue,
+                };
+                match message.message_type.as_str() {
+                    OUTPUT_STREAM_DATA => {
+                        send!(acknowledge(&message).serialize());
+                        if message.sequence_number < expected_sequence {
+                            continue;
+                        }
+                        out_of_order.insert(message.sequence_number, message);
+                        while let Some(message) = out_of_order.remove(&expected_sequence) {
+                            expected_sequence += 1;
+                            match message.payload_type {
+                                PAYLOAD_HANDSHAKE_REQUEST => {
+                                    match handshake_response(&message.payload, &mut handshake) {
+                                        Ok(response) => {
+                                            let message = ClientMessage::new(
+                                                INPUT_STREAM_DATA,
+                                                next_sequence,
+                                                FLAG_DATA,
+                                                PAYLOAD_HANDSHAKE_RESPONSE,
+                                                response,
+                                            );
+                                            let bytes = message.serialize();
+                                            unacked.insert(next_sequence, (bytes.clone(), Instant::now()));
+                                            next_sequence += 1;
+                                            send!(bytes);
+                                        }
+                                        Err(error) => {
+                                            let _ = events.send(ChannelEvent::Closed(Some(error)));
+                                            return;
+                                        }
+                                    }
+                                }
+                                PAYLOAD_HANDSHAKE_COMPLETE => {
+                                    let _ = events.send(ChannelEvent::Ready(handshake.clone()));
+                                }
+                                PAYLOAD_ENC_CHALLENGE_REQUEST => {
+                                    let _ = events.send(ChannelEvent::Closed(Some(
+                                        "KMS encrypted sessions are not supported, use --aws-cli".to_string(),
+                                    )));
+                                    return;
+                                }
+                                payload_type => {
+                                    let _ = events.send(ChannelEvent::Data(payload_type, message.payload));
+                                }
+                            }
+                        }
+                    }
+                    ACKNOWLEDGE => {
+                        let ack: Value = serde_json::from_slice(&message.payload).unwrap_or_default();
+                        if let Some(sequence) = ack["AcknowledgedMessageSequenceNumber"].as_i64() {
+                            unacked.remove(&sequence);
+                        }
+                    }
+                    CHANNEL_CLOSED => {
+                        let closed: Value = serde_json::from_slice(&message.payload).unwrap_or_default();
+                        let output = closed["Output"].as_str().filter(|o| !o.is_empty()).map(|o| o.to_string());
+                        let _ = events.send(ChannelEvent::Closed(output));
+                        return;
+                    }
+                    START_PUBLICATION => paused = false,
+                    PAUSE_PUBLICATION => paused = true,
+                    _ => {}
+                }
+            }
+            data = input.recv() => {
+                match data {
+                    Some(data) => pending.push_back(data),
+                    // The DataChannel was dropped
+                    None => {
+                        let _ = sink.close().await;
+                        return;
+                    }
+                }
+            }
+            _ = tick.tick() => {
+                for (bytes, sent) in unacked.values_mut() {
+                    if sent.elapsed() >= RESEND_TIMEOUT {
+                        *sent = Instant::now();
+                        let bytes = bytes.clone();
+                        send!(bytes);
+                    }
+                }
+                if last_ping.elapsed() >= PING_INTERVAL {
+                    last_ping = Instant::now();
+                    let _ = sink.send(Message::Ping(Vec::new())).await;
+                }
+            }
+        }
+
+        if paused {
+            continue;
+        }
+        while let Some((payload_type, payload)) = pending.pop_front() {
+            let message = ClientMessage::new(INPUT_STREAM_DATA, next_sequence, FLAG_DATA, payload_type, payload);
+            let bytes = message.serialize();
+            unacked.insert(next_sequence, (bytes.clone(), Instant::now()));
+            next_sequence += 1;
+            send!(bytes);
+        }
+    }
+}
+
+pub(crate) async fn start_ssm_session(
+    config: &SdkConfig,
+    target: &str,
+    document: Option<&str>,
+    parameters: &[(&str, &str)],
+) -> Result<Session, String> {
+    let client = aws_sdk_ssm::Client::new(config);
+    let parameters: HashMap<String, Vec<String>> = parameters
+        .iter()
+        .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+        .collect();
+    let output = client
+        .start_session()
+        .target(target)
+        .set_document_name(document.map(|d| d.to_string()))
+        .set_parameters((!parameters.is_empty()).then_some(parameters))
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    Ok(Session {
+        session_id: output.session_id().unwrap_or_default().to_string(),
+        stream_url: output.stream_url().unwrap_or_default().to_string(),
+        token_value: output.token_value().unwrap_or_default().to_string(),
+    })
+}
+
+pub(crate) async fn start_ecs_session(
+    config: &SdkConfig,
+    cluster: &str,
+    task: &str,
+    container: &str,
+    command: &str,
+) -> Result<Session, String> {
+    let client = aws_sdk_ecs::Client::new(config);
+    let output = client
+        .execute_command()
+        .cluster(cluster)
+        .task(task)
+        .container(container)
+        .command(command)
+        .interactive(true)
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    let session = output.session().ok_or("ECS returned no session")?;
+    Ok(Session {
+        session_id: session.session_id().unwrap_or_default().to_string(),
+        stream_url: session.stream_url().unwrap_or_default().to_string(),
+        token_value: session.token_value().unwrap_or_default().to_string(),
+    })
+}
+
+pub(crate) async fn terminate_session(config: &SdkConfig, session_id: &str) {
+    let client = aws_sdk_ssm::Client::new(config);
+    let _ = client.terminate_session().session_id(session_id).send().await;
+}
+
+fn terminal_size() -> Vec<u8> {
+    let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    json!({ "cols": cols, "rows": rows }).to_string().into_bytes()
+}
+
+fn write_output(payload_type: u32, payload: &[u8], exit_code: &mut i32) {
+    match payload_type {
+        PAYLOAD_OUTPUT => {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(payload);
+            let _ = stdout.flush();
+        }
+        PAYLOAD_STDERR => {
+            let _ = std::io::stderr().write_all(payload);
+        }
+        PAYLOAD_EXIT_CODE => {
+            *exit_code = String::from_utf8_lossy(payload).trim().parse().unwrap_or(*exit_code);
+        }
+        _ => {}
+    }
+}
+
+// Interactive shell: raw terminal, keystrokes go out as they are typed, the terminal size
+// follows the local window. Returns the exit code of the remote command when the agent sends it
+pub(crate) async fn run_shell(mut channel: DataChannel) -> Result<i32, String> {
+    let (_, early) = channel.wait_ready().await?;
+    let mut exit_code = 0;
+    for (payload_type, payload) in early {
+        write_output(payload_type, &payload, &mut exit_code);
+    }
+
+    let (stdin_tx, mut stdin_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buffer = [0u8; STREAM_DATA_PAYLOAD_SIZE];
+        while let Ok(read) = stdin.read(&mut buffer) {
+            if read == 0 || stdin_tx.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let _ = crossterm::terminal::enable_raw_mode();
+    let mut size = terminal_size();
+    channel.send(PAYLOAD_SIZE, &size);
+    let mut resize = tokio::time::interval(Duration::from_millis(500));
+    let reason = loop {
+        tokio::select! {
+            event = channel.recv() => match event {
+                ChannelEvent::Data(payload_type, payload) => write_output(payload_type, &payload, &mut exit_code),
+                ChannelEvent::Closed(reason) => break reason,
+                ChannelEvent::Ready(_) => {}
+            },
+            Some(keys) = stdin_rx.recv() => channel.send(PAYLOAD_OUTPUT, &keys),
+            _ = resize.tick() => {
+                let current = terminal_size();
+                if current != size {
+                    size = current;
+                    channel.send(PAYLOAD_SIZE, &size);
+                }
+            }
+        }
+    };
+    let _ = crossterm::terminal::disable_raw_mode();
+    if let Some(reason) = reason {
+        println!("\r\n{}", reason);
+    }
+    Ok(exit_code)
+}
+
+fn smux_frame(command: u8, stream_id: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(SMUX_HEADER_LENGTH + data.len());
+    frame.push(SMUX_VERSION);
+    frame.push(command);
+    frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    frame.extend_from_slice(&stream_id.to_le_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+// Frames can span several data channel messages, a frame is only taken once it's complete
+fn next_smux_frame(buffer: &mut Vec<u8>) -> Option<(u8, u32, Vec<u8>)> {
+    if buffer.len() < SMUX_HEADER_LENGTH {
+        return None;
+    }
+    let length = u16::from_le_bytes([buffer[2], buffer[3]]) as usize;
+    if buffer.len() < SMUX_HEADER_LENGTH + length {
+        return None;
+    }
+    let command = buffer[1];
+    let stream_id = u32::from_le_bytes(buffer[4..8].try_into().unwrap());
+    let data = buffer.drain(..SMUX_HEADER_LENGTH + length).skip(SMUX_HEADER_LENGTH).collect();
+    Some((command, stream_id, data))
+}
+
+// Bytes read from a local connection, None once it's closed
+type StreamData = (u32, Option<Vec<u8>>);
+
+// Bytes forwarded by a port forward session, read by the forward view
+#[derive(Default)]
+pub(crate) struct Traffic {
+    pub(crate) sent: AtomicU64,
+    pub(crate) received: AtomicU64,
+}
+
+impl Traffic {
+    fn add(counter: &AtomicU64, bytes: usize) {
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+fn spawn_connection(
+    stream_id: u32,
+    socket: tokio::net::TcpStream,
+    from_tcp: mpsc::UnboundedSender<StreamData>,
+) -> mpsc::UnboundedSender<Option<Vec<u8>>> {
+    let (mut reader, mut writer) = socket.into_split();
+    let (to_tcp, mut to_tcp_rx) = mpsc::unbounded_channel::<Option<Vec<u8>>>();
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; STREAM_DATA_PAYLOAD_SIZE];
+        loop {
+            match reader.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    if from_tcp.send((stream_id, Some(buffer[..read].to_vec()))).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        let _ = from_tcp.send((stream_id, None));
+    });
+    tokio::spawn(async move {
+        while let Some(Some(data)) = to_tcp_rx.recv().await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+    to_tcp
+}
+
+// Every local connection becomes a smux stream of the data channel. Client stream IDs are odd
+async fn forward_multiplexed(channel: &mut DataChannel, listener: &TcpListener, traffic: &Traffic) -> Option<String> {
+    let (from_tcp, mut from_tcp_rx) = mpsc::unbounded_channel::<StreamData>();
+    let mut streams: HashMap<u32, mpsc::UnboundedSender<Option<Vec<u8>>>> = HashMap::new();
+    let mut next_stream_id: u32 = 1;
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut keepalive = tokio::time::interval(SMUX_KEEPALIVE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((socket, _)) = accepted else { continue };
+                let stream_id = next_stream_id;
+                next_stream_id += 2;
+                channel.send(PAYLOAD_OUTPUT, &smux_frame(SMUX_SYN, stream_id, &[]));
+                streams.insert(stream_id, spawn_connection(stream_id, socket, from_tcp.clone()));
+            }
+            Some((stream_id, data)) = from_tcp_rx.recv() => match data {
+                Some(data) => {
+                    Traffic::add(&traffic.sent, data.len());
+                    channel.send(PAYLOAD_OUTPUT, &smux_frame(SMUX_PSH, stream_id, &data));
+                }
+                None => {
+                    if streams.remove(&stream_id).is_some() {
+                        channel.send(PAYLOAD_OUTPUT, &smux_frame(SMUX_FIN, stream_id, &[]));
+                    }
+                }
+            },
+            event = channel.recv() => match event {
+                ChannelEvent::Data(PAYLOAD_OUTPUT, payload) => {
+                    buffer.extend_from_slice(&payload);
+                    while let Some((command, stream_id, data)) = next_smux_frame(&mut buffer) {
+                        match command {
+                            SMUX_PSH => {
+                                if let Some(stream) = streams.get(&stream_id) {
+                                    Traffic::add(&traffic.received, data.len());
+                                    let _ = stream.send(Some(data));
+                                }
+                            }
+                            SMUX_FIN => {
+                                if let Some(stream) = streams.remove(&stream_id) {
+                                    let _ = stream.send(None);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                ChannelEvent::Data(_, _) | ChannelEvent::Ready(_) => {}
+                ChannelEvent::Closed(reason) => return reason,
+            },
+            _ = keepalive.tick() => channel.send(PAYLOAD_OUTPUT, &smux_frame(SMUX_NOP, 0, &[])),
+        }
+    }
+}
+
+// Older agents forward a single connection at a time, the agent is told when it closes
+async fn forward_single(channel: &mut DataChannel, listener: &TcpListener, traffic: &Traffic) -> Option<String> {
+    let (from_tcp, mut from_tcp_rx) = mpsc::unbounded_channel::<StreamData>();
+    let mut connection: Option<mpsc::UnboundedSender<Option<Vec<u8>>>> = None;
+    let mut connection_id: u32 = 0;
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept(), if connection.is_none() => {
+                let Ok((socket, _)) = accepted else { continue };
+                connection_id += 1;
+                connection = Some(spawn_connection(connection_id, socket, from_tcp.clone()));
+            }
+            Some((id, data)) = from_tcp_rx.recv() => match data {
+                Some(data) if id == connection_id => {
+                    Traffic::add(&traffic.sent, data.len());
+                    channel.send(PAYLOAD_OUTPUT, &data);
+                }
+                None if id == connection_id => {
+                    connection = None;
+                    channel.send(PAYLOAD_FLAG, &FLAG_DISCONNECT_TO_PORT.to_be_bytes());
+                }
+                _ => {}
+            },
+            event = channel.recv() => match event {
+                ChannelEvent::Data(PAYLOAD_OUTPUT, payload) => {
+                    if let Some(connection) = &connection {
+                        Traffic::add(&traffic.received, payload.len());
+                        let _ = connection.send(Some(payload));
+                    }
+                }
+                ChannelEvent::Data(_, _) | ChannelEvent::Ready(_) => {}
+                ChannelEvent::Closed(reason) => return reason,
+            },
+        }
+    }
+}
+
+// Forward the connections of `listener` until the session closes, returns why it closed.
+// The listener outlives the session so a new one can take over the same port
+pub(crate) async fn forward_port(
+    channel: &mut DataChannel,
+    handshake: &Handshake,
+    listener: &TcpListener,
+    traffic: &Traffic,
+) -> Option<String> {
+    if handshake.multiplexing() {
+        forward_multiplexed(channel, listener, traffic).await
+    } else {
+        forward_single(channel, listener, traffic).await
+    }
+}
+
+pub(crate) async fn start_port_session(
+    config: &SdkConfig,
+    target: &str,
+    host: &str,
+    local_port: u16,
+    remote_port: u16,
+) -> Result<Session, String> {
+    let (local_port, remote_port) = (local_port.to_string(), remote_port.to_string());
+    let parameters = [("portNumber", remote_port.as_str()), ("localPortNumber", local_port.as_str()), ("host", host)];
+    start_ssm_session(config, target, Some("AWS-StartPortForwardingSessionToRemoteHost"), &parameters).await
+}
+
+pub(crate) fn into_async_listener(listener: std::net::TcpListener) -> Result<TcpListener, String> {
+    listener.set_nonblocking(true).map_err(|error| error.to_string())?;
+    TcpListener::from_std(listener).map_err(|error| error.to_string())
+}
+
+// Forward the connections of `listener` until the session closes or Ctrl-C
+pub(crate) async fn run_port_forward(
+    mut channel: DataChannel,
+    listener: TcpListener,
+    session_id: &str,
+    remote: &str,
+) -> Result<(), String> {
+    let (handshake, _) = channel.wait_ready().await?;
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or_default();
+    println!("Port {} opened for sessionId {}.", port, session_id);
+    println!("Forwarding {} to {}, waiting for connections...", format!("localhost:{}", port).bold(), remote);
+
+    let traffic = Traffic::default();
+    tokio::select! {
+        reason = forward_port(&mut channel, &handshake, &listener, &traffic) => match reason {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        },
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+// Run an interactive session and exit with its status, like run_aws_cli does
+pub(crate) async fn exit_with_shell(config: &SdkConfig, session: Result<Session, String>) -> ! {
+    let session = match session {
+        Ok(session) => session,
+        Err(error) => {
+            println!("Can't start the session: {}", error);
+            std::process::exit(1);
+        }
+    };
+    println!("Starting session with SessionId: {}", session.session_id);
+    let result = match DataChannel::open(&session).await {
+        Ok(channel) => run_shell(channel).await,
+        Err(error) => Err(error),
+    };
+    terminate_session(config, &session.session_id).await;
+    match result {
+        Ok(exit_code) => {
+            println!("Exiting session with sessionId: {}.", session.session_id);
+            std::process::exit(exit_code);
+        }
+        Err(error) => {
+            println!("Session {} failed: {}", session.session_id, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Forward the connections of the bound local port to host:remote_port through the target until Ctrl-C
+pub(crate) async fn exit_with_port_forward(
+    config: &SdkConfig,
+    target: &str,
+    host: &str,
+    listener: std::net::TcpListener,
+    remote_port: u16,
+) -> ! {
+    let local_port = listener.local_addr().map(|address| address.port()).unwrap_or_default();
+    let listener = match into_async_listener(listener) {
+        Ok(listener) => listener,
+        Err(error) => {
+            println!("Can't listen on localhost:{}: {}", local_port, error);
+            std::process::exit(1);
+        }
+    };
+    let session = match start_port_session(config, target, host, local_port, remote_port).await {
+        Ok(session) => session,
+        Err(error) => {
+            println!("Can't start the session: {}", error);
+            std::process::exit(1);
+        }
+    };
+    println!("Starting session with SessionId: {}", session.session_id);
+    let result = match DataChannel::open(&session).await {
+        Ok(channel) => run_port_forward(channel, listener, &session.session_id, &format!("{}:{}", host, remote_port)).await,
+        Err(error) => Err(error),
+    };
+    terminate_session(config, &session.session_id).await;
+    match result {
+        Ok(_) => {
+            println!("Exiting session with sessionId: {}.", session.session_id);
+            std::process::exit(0);
+        }
+        Err(error) => {
+            println!("Session {} failed: {}", session.session_id, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::WebSocketStream;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Local websocket standing in for the Session Manager agent
+    struct Agent {
+        socket: WebSocketStream<TcpStream>,
+        sequence: i64,
+    }
+
+    impl Agent {
+        async fn start() -> (Self, DataChannel) {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let session = Session {
+                session_id: "test-session".to_string(),
+                stream_url: format!("ws://{}", listener.local_addr().unwrap()),
+                token_value: "token".to_string(),
+            };
+            let (accepted, channel) = tokio::join!(
+                async {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio_tungstenite::accept_async(stream).await.unwrap()
+                },
+                DataChannel::open(&session)
+            );
+            let mut agent = Self { socket: accepted, sequence: 0 };
+            let Some(Ok(Message::Text(open))) = agent.socket.next().await else {
+                panic!("the channel wasn't opened with a text message");
+            };
+            let open: Value = serde_json::from_str(&open).unwrap();
+            assert_eq!(open["TokenValue"], "token");
+            assert_eq!(open["ClientVersion"], CLIENT_VERSION);
+            (agent, channel.unwrap())
+        }
+
+        async fn send_at(&mut self, sequence: i64, payload_type: u32, payload: &[u8]) {
+            let message = ClientMessage::new(OUTPUT_STREAM_DATA, sequence, FLAG_DATA, payload_type, payload.to_vec());
+            self.socket.send(Message::Binary(message.serialize())).await.unwrap();
+        }
+
+        async fn send(&mut self, payload_type: u32, payload: &[u8]) {
+            self.send_at(self.sequence, payload_type, payload).await;
+            self.sequence += 1;
+        }
+
+        async fn ack(&mut self, message: &ClientMessage) {
+            let ack = acknowledge(message);
+            self.socket.send(Message::Binary(ack.serialize())).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> ClientMessage {
+            loop {
+                let frame = tokio::time::timeout(TIMEOUT, self.socket.next()).await.expect("no message from the client");
+                if let Some(Ok(Message::Binary(bytes))) = frame {
+                    return ClientMessage::deserialize(&bytes).unwrap();
+                }
+            }
+        }
+
+        // Next input of the client, acked like the agent does
+        async fn recv_input(&mut self) -> ClientMessage {
+            loop {
+                let message = self.recv().await;
+                if message.message_type == INPUT_STREAM_DATA {
+                    self.ack(&message).await;
+                    return message;
+                }
+            }
+        }
+
+        async fn handshake(&mut self, channel: &mut DataChannel, agent_version: &str) -> Handshake {
+            let request = json!({
+                "AgentVersion": agent_version,
+                "RequestedClientActions": [{
+                    "ActionType": "SessionType",
+                    "ActionParameters": {"SessionType": "Port", "Properties": {"Type": "LocalPortForwarding"}},
+                }],
+            });
+            self.send(PAYLOAD_HANDSHAKE_REQUEST, request.to_string().as_bytes()).await;
+            let response = self.recv_input().await;
+            assert_eq!(response.payload_type, PAYLOAD_HANDSHAKE_RESPONSE);
+            self.send(PAYLOAD_HANDSHAKE_COMPLETE, b"{}").await;
+            let (handshake, _) = tokio::time::timeout(TIMEOUT, channel.wait_ready()).await.unwrap().unwrap();
+            handshake
+        }
+    }
+
+    async fn recv_data(channel: &mut DataChannel) -> (u32, Vec<u8>) {
+        match tokio::time::timeout(TIMEOUT, channel.recv()).await.expect("no event from the channel") {
+            ChannelEvent::Data(payload_type, payload) => (payload_type, payload),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn client_message_round_trip() {
+        let message = ClientMessage::new(INPUT_STREAM_DATA, 42, FLAG_DATA, PAYLOAD_OUTPUT, b"echo hello".to_vec());
+        let bytes = message.serialize();
+        assert_eq!(bytes.len(), HEADER_LENGTH + 4 + 10);
+        assert_eq!(&bytes[..4], &(HEADER_LENGTH as u32).to_be_bytes());
+        assert_eq!(ClientMessage::deserialize(&bytes).unwrap(), message);
+
+        let empty = ClientMessage::new(ACKNOWLEDGE, 0, FLAG_ACK, 0, Vec::new());
+        assert_eq!(ClientMessage::deserialize(&empty.serialize()).unwrap(), empty);
+    }
+
+    #[test]
+    fn client_message_rejects_digest_mismatch() {
+        let mut bytes = ClientMessage::new(OUTPUT_STREAM_DATA, 0, FLAG_DATA, PAYLOAD_OUTPUT, b"payload".to_vec()).serialize();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        assert_eq!(ClientMessage::deserialize(&bytes).unwrap_err(), "payload digest mismatch");
+    }
+
+    #[test]
+    fn client_message_rejects_truncated_messages() {
+        let bytes = ClientMessage::new(OUTPUT_STREAM_DATA, 0, FLAG_DATA, PAYLOAD_OUTPUT, b"payload".to_vec()).serialize();
+        assert!(ClientMessage::deserialize(&bytes[..HEADER_LENGTH - 10]).is_err());
+        assert!(ClientMessage::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn handshake_response_accepts_session_type() {
+        let request = json!({
+            "AgentVersion": "3.2.582.0",
+            "RequestedClientActions": [{
+                "ActionType": "SessionType",
+                "ActionParameters": {"SessionType": "Port", "Properties": {"Type": "LocalPortForwarding"}},
+            }],
+        });
+        let mut handshake = Handshake::default();
+        let response = handshake_response(request.to_string().as_bytes(), &mut handshake).unwrap();
+        let response: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["ClientVersion"], CLIENT_VERSION);
+        assert_eq!(response["Errors"], json!([]));
+        assert_eq!(response["ProcessedClientActions"][0]["ActionType"], "SessionType");
+        assert_eq!(response["ProcessedClientActions"][0]["ActionStatus"], ACTION_SUCCESS);
+        assert_eq!(handshake.agent_version, "3.2.582.0");
+        assert_eq!(handshake.session_type, "Port");
+        assert!(handshake.multiplexing());
+
+        handshake.agent_version = "3.0.161.0".to_string();
+        assert!(!handshake.multiplexing());
+    }
+
+    #[test]
+    fn handshake_response_rejects_kms_encryption() {
+        let request = json!({
+            "AgentVersion": "3.2.582.0",
+            "RequestedClientActions": [{"ActionType": "KMSEncryption", "ActionParameters": {"KMSKeyId": "key"}}],
+        });
+        let error = handshake_response(request.to_string().as_bytes(), &mut Handshake::default()).unwrap_err();
+        assert!(error.contains("KMSEncryption"));
+    }
+
+    #[test]
+    fn smux_frame_split_in_two() {
+        let frame = smux_frame(SMUX_PSH, 3, b"hello smux");
+        let mut buffer = frame[..5].to_vec();
+        assert!(next_smux_frame(&mut buffer).is_none());
+        buffer.extend_from_slice(&frame[5..]);
+        buffer.extend_from_slice(&smux_frame(SMUX_FIN, 3, &[]));
+        assert_eq!(next_smux_frame(&mut buffer), Some((SMUX_PSH, 3, b"hello smux".to_vec())));
+        assert_eq!(next_smux_frame(&mut buffer), Some((SMUX_FIN, 3, Vec::new())));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn channel_acks_and_reorders_output() {
+        let (mut agent, mut channel) = Agent::start().await;
+        agent.send_at(1, PAYLOAD_OUTPUT, b"world").await;
+        agent.send_at(0, PAYLOAD_OUTPUT, b"hello ").await;
+        // Sent again after the client acked it, it must not be delivered twice
+        agent.send_at(0, PAYLOAD_OUTPUT, b"hello ").await;
+        agent.send_at(2, PAYLOAD_STDERR, b"!").await;
+
+        let mut acked = Vec::new();
+        for _ in 0..4 {
+            let ack = agent.recv().await;
+            assert_eq!(ack.message_type, ACKNOWLEDGE);
+            let payload: Value = serde_json::from_slice(&ack.payload).unwrap();
+            acked.push(payload["AcknowledgedMessageSequenceNumber"].as_i64().unwrap());
+        }
+        assert_eq!(acked, vec![1, 0, 0, 2]);
+
+        assert_eq!(recv_data(&mut channel).await, (PAYLOAD_OUTPUT, b"hello ".to_vec()));
+        assert_eq!(recv_data(&mut channel).await, (PAYLOAD_OUTPUT, b"world".to_vec()));
+        assert_eq!(recv_data(&mut channel).await, (PAYLOAD_STDERR, b"!".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn channel_resends_unacked_input() {
+        let (mut agent, channel) = Agent::start().await;
+        channel.send(PAYLOAD_OUTPUT, b"ls\n");
+        let first = agent.recv().await;
+        assert_eq!((first.message_type.as_str(), first.sequence_number), (INPUT_STREAM_DATA, 0));
+        assert_eq!(first.payload, b"ls\n");
+
+        // Not acked: the same message comes again
+        let resent = agent.recv().await;
+        assert_eq!(resent, first);
+        agent.ack(&resent).await;
+
+        // Acked: the next message is new input, not another resend
+        tokio::time::sleep(RESEND_TIMEOUT + Duration::from_millis(600)).await;
+        channel.send(PAYLOAD_OUTPUT, b"pwd\n");
+        let next = agent.recv().await;
+        assert_eq!(next.sequence_number, 1);
+        assert_eq!(next.payload, b"pwd\n");
+    }
+
+    #[tokio::test]
+    async fn channel_splits_large_input() {
+        let (mut agent, channel) = Agent::start().await;
+        let input = vec![b'x'; STREAM_DATA_PAYLOAD_SIZE + 10];
+        channel.send(PAYLOAD_OUTPUT, &input);
+        let first = agent.recv_input().await;
+        let second = agent.recv_input().await;
+        assert_eq!((first.sequence_number, first.payload.len()), (0, STREAM_DATA_PAYLOAD_SIZE));
+        assert_eq!((second.sequence_number, second.payload.len()), (1, 10));
+    }
+
+    #[tokio::test]
+    async fn multiplexed_forward_reassembles_split_frames() {
+        let (mut agent, mut channel) = Agent::start().await;
+        let handshake = agent.handshake(&mut channel, "3.2.582.0").await;
+        assert!(handshake.multiplexing());
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let forward = tokio::spawn(async move {
+            let traffic = Traffic::default();
+            forward_port(&mut channel, &handshake, &listener, &traffic).await
+        });
+        let mut local = TcpStream::connect(address).await.unwrap();
+
+        // Keepalives may come first
+        let stream_id = loop {
+            let input = agent.recv_input().await;
+            let mut buffer = input.payload;
+            if let Some((SMUX_SYN, stream_id, _)) = next_smux_frame(&mut buffer) {
+                break stream_id;
+            }
+        };
+        assert_eq!(stream_id, 1);
+
+        let frame = smux_frame(SMUX_PSH, stream_id, b"hello smux");
+        agent.send(PAYLOAD_OUTPUT, &frame[..5]).await;
+        agent.send(PAYLOAD_OUTPUT, &frame[5..]).await;
+        let mut received = vec![0u8; 10];
+        tokio::time::timeout(TIMEOUT, local.read_exact(&mut received)).await.unwrap().unwrap();
+        assert_eq!(received, b"hello smux");
+
+        local.write_all(b"ping").await.unwrap();
+        let data = loop {
+            let mut buffer = agent.recv_input().await.payload;
+            if let Some((SMUX_PSH, 1, data)) = next_smux_frame(&mut buffer) {
+                break data;
+            }
+        };
+        assert_eq!(data, b"ping");
+
+        let closed = json!({"Output": "session terminated"});
+        let message = ClientMessage::new(CHANNEL_CLOSED, 0, FLAG_DATA, 0, closed.to_string().into_bytes());
+        agent.socket.send(Message::Binary(message.serialize())).await.unwrap();
+        let reason = tokio::time::timeout(TIMEOUT, forward).await.unwrap().unwrap();
+        assert_eq!(reason.as_deref(), Some("session terminated"));
+    }
+}
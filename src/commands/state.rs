@@ -0,0 +1,688 @@
+use crate::commands::aws_utils::{error_message, load_config, S3Clients};
+use crate::commands::cli_utils::{arg_or_prompt, is_interactive};
+use crate::commands::inti_aws_state::prompt_bucket_name;
+use crate::commands::state_backend::list_states;
+use crate::commands::state_history::state_history;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
+use ratatui::{
+    layout::{Alignment, Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const ROOT_MODULE: &str = "root";
+const OUTPUTS: &str = "outputs";
+pub(crate) const SENSITIVE: &str = "(sensitive)";
+
+// One instance of a resource, `value` is the instance object of the state (attributes, dependencies...)
+pub(crate) struct StateResource {
+    pub(crate) module: String,
+    pub(crate) address: String,
+    pub(crate) value: Value,
+}
+
+pub(crate) struct TerraformState {
+    pub(crate) serial: u64,
+    pub(crate) lineage: String,
+    pub(crate) terraform_version: String,
+    pub(crate) resources: Vec<StateResource>,
+    pub(crate) outputs: BTreeMap<String, Value>,
+}
+
+impl TerraformState {
+    // Outputs first, then the root module and the child modules
+    fn modules(&self) -> Vec<String> {
+        let mut modules: Vec<String> = Vec::new();
+        if !self.outputs.is_empty() {
+            modules.push(OUTPUTS.to_string());
+        }
+        for resource in &self.resources {
+            if !modules.contains(&resource.module) {
+                modules.push(resource.module.clone());
+            }
+        }
+        modules[usize::from(!self.outputs.is_empty())..].sort_by(|a, b| {
+            (*a != ROOT_MODULE, a).cmp(&(*b != ROOT_MODULE, b))
+        });
+        modules
+    }
+
+    fn items(&self, module: &str) -> Vec<String> {
+        if module == OUTPUTS {
+            return self.outputs.keys().cloned().collect();
+        }
+        self.resources
+            .iter()
+            .filter(|r| r.module == module)
+            .map(|r| r.address.clone())
+            .collect()
+    }
+
+    // Sensitive outputs and attributes are never displayed
+    fn detail(&self, module: &str, item: &str) -> Option<Value> {
+        if module == OUTPUTS {
+            let output = self.outputs.get(item)?;
+            if output.get("sensitive").and_then(|s| s.as_bool()).unwrap_or(false) {
+                let mut output = output.clone();
+                output["value"] = Value::String(SENSITIVE.to_string());
+                return Some(output);
+            }
+            return Some(output.clone());
+        }
+        self.resources
+            .iter()
+            .find(|r| r.address == item)
+            .map(|r| mask_sensitive(&r.value))
+    }
+}
+
+pub(crate) enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+// Paths of the attributes listed in the sensitive_attributes of an instance, e.g. password or tags["token"]
+pub(crate) fn sensitive_paths(instance: &Value) -> Vec<Vec<PathStep>> {
+    let paths = instance.get("sensitive_attributes").and_then(|p| p.as_array());
+    paths
+        .into_iter()
+        .flatten()
+        .filter_map(|path| {
+            path.as_array()?
+                .iter()
+                .map(|step| match (step.get("type")?.as_str()?, step.get("value")?) {
+                    ("get_attr", Value::String(name)) => Some(PathStep::Key(name.clone())),
+                    ("index", index) => match index.get("value")? {
+                        Value::Number(number) => Some(PathStep::Index(number.as_u64()? as usize)),
+                        Value::String(key) => Some(PathStep::Key(key.clone())),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Instance whose sensitive attributes are replaced, like the value of sensitive outputs
+pub(crate) fn mask_sensitive(instance: &Value) -> Value {
+    let mut masked = instance.clone();
+    for path in sensitive_paths(instance) {
+        let mut value = masked.get_mut("attributes");
+        for step in &path {
+            value = match step {
+                PathStep::Key(key) => value.and_then(|v| v.get_mut(key.as_str())),
+                PathStep::Index(index) => value.and_then(|v| v.get_mut(*index)),
+            };
+        }
+        if let Some(value) = value {
+            *value = Value::String(SENSITIVE.to_string());
+        }
+    }
+    masked
+}
+
+// module.a.module.b + data.aws_ami.this + ["key"] or [0]
+fn resource_address(resource: &Value, instance: &Value) -> String {
+    let mut address = String::new();
+    if let Some(module) = resource.get("module").and_then(|m| m.as_str()) {
+        address.push_str(module);
+        address.push('.');
+    }
+    if resource.get("mode").and_then(|m| m.as_str()) == Some("data") {
+        address.push_str("data.");
+    }
+    address.push_str(resource.get("type").and_then(|t| t.as_str()).unwrap_or_default());
+    address.push('.');
+    address.push_str(resource.get("name").and_then(|n| n.as_str()).unwrap_or_default());
+    match instance.get("index_key") {
+        Some(Value::Number(index)) => address.push_str(&format!("[{}]", index)),
+        Some(Value::String(key)) => address.push_str(&format!("[\"{}\"]", key)),
+        _ => {}
+    }
+    address
+}
+
+// Only the format of terraform >= 0.12 (state version 4) is supported
+pub(crate) fn parse_state(content: &str) -> Result<TerraformState, String> {
+    let state: Value = serde_json::from_str(content).map_err(|error| format!("invalid state: {}", error))?;
+    let version = state.get("version").and_then(|v| v.as_u64()).unwrap_or_default();
+    if version != 4 {
+        return Err(format!("unsupported state version {}", version));
+    }
+
+    let mut resources = Vec::new();
+    for resource in state.get("resources").and_then(|r| r.as_array()).into_iter().flatten() {
+        let module = resource
+            .get("module")
+            .and_then(|m| m.as_str())
+            .unwrap_or(ROOT_MODULE)
+            .to_string();
+        for instance in resource.get("instances").and_then(|i| i.as_array()).into_iter().flatten() {
+            resources.push(StateResource {
+                module: module.clone(),
+                address: resource_address(resource, instance),
+                value: instance.clone(),
+            });
+        }
+    }
+    let outputs = state
+        .get("outputs")
+        .and_then(|o| o.as_object())
+        .map(|o| o.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default();
+
+    Ok(TerraformState {
+        serial: state.get("serial").and_then(|s| s.as_u64()).unwrap_or_default(),
+        lineage: state.get("lineage").and_then(|l| l.as_str()).unwrap_or_default().to_string(),
+        terraform_version: state
+            .get("terraform_version")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        resources,
+        outputs,
+    })
+}
+
+pub(crate) async fn download_state(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    key: &str,
+    version_id: Option<String>,
+) -> Result<String, String> {
+    let object = client
+        .get_object()
+        .bucket(bucket_name)
+        .key(key)
+        .set_version_id(version_id)
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    let body = object.body.collect().await.map_err(|error| error.to_string())?.into_bytes();
+    String::from_utf8(body.to_vec()).map_err(|error| error.to_string())
+}
+
+// Where the states come from: a bucket, or a local file for offline use
+enum Source {
+    Bucket { client: aws_sdk_s3::Client, bucket: String },
+    File(String),
+}
+
+impl Source {
+    async fn read(&self, key: &str) -> Result<TerraformState, String> {
+        let content = match self {
+            Source::Bucket { client, bucket } => download_state(client, bucket, key, None).await?,
+            Source::File(path) => std::fs::read_to_string(path).map_err(|error| error.to_string())?,
+        };
+        parse_state(&content)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+enum Page {
+    #[default]
+    States = 0,
+    Modules = 1,
+    Resources = 2,
+}
+
+impl Page {
+    fn next(self) -> Self {
+        match self {
+            Page::States => Page::Modules,
+            Page::Modules => Page::Resources,
+            Page::Resources => Page::Resources,
+        }
+    }
+    fn prev(self) -> Self {
+        match self {
+            Page::States => Page::States,
+            Page::Modules => Page::States,
+            Page::Resources => Page::Modules,
+        }
+    }
+    fn title(&self) -> &'static str {
+        match self {
+            Page::States => "States",
+            Page::Modules => "Modules",
+            Page::Resources => "Resources",
+        }
+    }
+}
+
+#[derive(Default)]
+struct AppState {
+    page: Page,
+    states: Vec<String>,
+    // Index in `states` of the parsed state
+    loaded: Option<(usize, TerraformState)>,
+    // Selected values, the lists are filtered by the search
+    state_key: Option<String>,
+    module: Option<String>,
+    item: Option<String>,
+    idx: usize,
+    search: String,
+    searching: bool,
+    detail_scroll: u16,
+    message: String,
+}
+
+fn clamp_index(idx: usize, len: usize) -> usize {
+    if len == 0 { 0 } else { idx.min(len - 1) }
+}
+
+// Items of the current page matching the search
+fn visible_items(state: &AppState) -> Vec<String> {
+    let items = match (state.page, &state.loaded, &state.module) {
+        (Page::States, _, _) => state.states.clone(),
+        (Page::Modules, Some((_, tfstate)), _) => tfstate.modules(),
+        (Page::Resources, Some((_, tfstate)), Some(module)) => tfstate.items(module),
+        _ => Vec::new(),
+    };
+    let search = state.search.to_lowercase();
+    items
+        .into_iter()
+        .filter(|i| i.to_lowercase().contains(&search))
+        .collect()
+}
+
+fn select(state: &mut AppState) {
+    let items = visible_items(state);
+    state.idx = clamp_index(state.idx, items.len());
+    let selected = items.get(state.idx).cloned();
+    match state.page {
+        Page::States => state.state_key = selected,
+        Page::Modules => state.module = selected,
+        Page::Resources => state.item = selected,
+    }
+    state.detail_scroll = 0;
+}
+
+fn change_page(state: &mut AppState, page: Page) {
+    if page == state.page {
+        return;
+    }
+    state.page = page;
+    state.search.clear();
+    state.searching = false;
+    // Come back on the item selected when the page was left
+    let items = visible_items(state);
+    let selected = match page {
+        Page::States => &state.state_key,
+        Page::Modules => &state.module,
+        Page::Resources => &state.item,
+    };
+    state.idx = selected
+        .as_ref()
+        .and_then(|s| items.iter().position(|i| i == s))
+        .unwrap_or(0);
+    select(state);
+}
+
+fn handle_events(state: &mut AppState) -> std::io::Result<bool> {
+    let Event::Key(key) = event::read()? else {
+        return Ok(false);
+    };
+    if key.kind != KeyEventKind::Press {
+        return Ok(false);
+    }
+    if state.searching {
+        match key.code {
+            KeyCode::Esc => {
+                state.search.clear();
+                state.searching = false;
+            }
+            KeyCode::Enter => state.searching = false,
+            KeyCode::Backspace => {
+                state.search.pop();
+            }
+            KeyCode::Char(c) => state.search.push(c),
+            _ => return Ok(false),
+        }
+        state.idx = 0;
+        select(state);
+        return Ok(false);
+    }
+    match key.code {
+        KeyCode::Char('q') => return Ok(true),
+        KeyCode::Char('/') => state.searching = true,
+        KeyCode::Esc => {
+            state.search.clear();
+            select(state);
+        }
+        KeyCode::Left => change_page(state, state.page.prev()),
+        KeyCode::Right | KeyCode::Enter => change_page(state, state.page.next()),
+        KeyCode::Up => {
+            state.idx = state.idx.saturating_sub(1);
+            select(state);
+        }
+        KeyCode::Down => {
+            state.idx += 1;
+            select(state);
+        }
+        KeyCode::PageDown => state.detail_scroll = state.detail_scroll.saturating_add(10),
+        KeyCode::PageUp => state.detail_scroll = state.detail_scroll.saturating_sub(10),
+        _ => {}
+    }
+    Ok(false)
+}
+
+fn draw_list_block<'a>(title: String, items: &'a [String], selected: usize) -> (List<'a>, ListState) {
+    let list_items: Vec<ListItem> = items.iter().map(|i| ListItem::new(i.clone())).collect();
+    let mut ls = ListState::default();
+    if !items.is_empty() {
+        ls.select(Some(clamp_index(selected, items.len())));
+    } else {
+        ls.select(None);
+    }
+    let list = List::new(list_items)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow));
+    (list, ls)
+}
+
+fn detail_lines(state: &AppState) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    if !state.message.is_empty() {
+        lines.push(Line::from(Span::styled(state.message.clone(), Style::default().fg(Color::Red))));
+        lines.push(Line::from(""));
+    }
+    let Some((_, tfstate)) = &state.loaded else {
+        lines.push(Line::from("Press Enter to open the selected state"));
+        return lines;
+    };
+    match state.page {
+        Page::States | Page::Modules => {
+            lines.push(Line::from(format!("State:      {}", state.state_key.as_deref().unwrap_or("None"))));
+            lines.push(Line::from(format!("Serial:     {}", tfstate.serial)));
+            lines.push(Line::from(format!("Lineage:    {}", tfstate.lineage)));
+            lines.push(Line::from(format!("Terraform:  {}", tfstate.terraform_version)));
+            lines.push(Line::from(format!("Resources:  {}", tfstate.resources.len())));
+            lines.push(Line::from(format!("Outputs:    {}", tfstate.outputs.len())));
+            if state.page == Page::Modules {
+                let module = state.module.as_deref().unwrap_or("None");
+                lines.push(Line::from(""));
+                lines.push(Line::from(format!("Module:     {}", module)));
+                lines.push(Line::from(format!("Items:      {}", tfstate.items(module).len())));
+            }
+        }
+        Page::Resources => {
+            let detail = match (&state.module, &state.item) {
+                (Some(module), Some(item)) => tfstate.detail(module, item),
+                _ => None,
+            };
+            let json = detail
+                .map(|d| serde_json::to_string_pretty(&d).unwrap_or_default())
+                .unwrap_or_else(|| "None".to_string());
+            lines.extend(json.lines().map(|l| Line::from(l.to_string())));
+        }
+    }
+    lines
+}
+
+fn draw_state(frame: &mut Frame, state: &AppState) {
+    use Constraint::{Fill, Length, Min};
+
+    let vertical = Layout::vertical([Min(0), Length(3), Length(3)]);
+    let [main_area, search_area, status_area] = vertical.areas(frame.area());
+    let horizontal = Layout::horizontal([Fill(1); 2]);
+    let [left_area, right_area] = horizontal.areas(main_area);
+
+    // left: current page list, filtered by the search
+    let items = visible_items(state);
+    let title = match state.page {
+        Page::States => state.page.title().to_string(),
+        Page::Modules => format!("Modules of {}", state.state_key.as_deref().unwrap_or_default()),
+        Page::Resources => format!("Resources of {}", state.module.as_deref().unwrap_or_default()),
+    };
+    let (list, mut list_state) = draw_list_block(title, &items, state.idx);
+    frame.render_stateful_widget(list, left_area, &mut list_state);
+
+    // right: JSON of the selected resource/output, or a summary of the state
+    let para = Paragraph::new(detail_lines(state))
+        .scroll((state.detail_scroll, 0))
+        .block(Block::bordered().title("Details"));
+    frame.render_widget(para, right_area);
+
+    let search = if state.searching || !state.search.is_empty() {
+        format!("/{}", state.search)
+    } else {
+        "←/→ change page, ↑/↓ move, Enter open, / search, Esc clear, PgUp/PgDn scroll, q quit".to_string()
+    };
+    frame.render_widget(Paragraph::new(search).block(Block::bordered()), search_area);
+
+    // Footer: one box per page, highlight the current one
+    let footer_chunks = Layout::horizontal([
+        Constraint::Percentage(33),
+        Constraint::Percentage(34),
+        Constraint::Percentage(33),
+    ])
+        .split(status_area);
+
+    let sel_style = Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let normal_style = Style::default();
+
+    let pages = [
+        (Page::States, Page::States.title()),
+        (Page::Modules, Page::Modules.title()),
+        (Page::Resources, Page::Resources.title()),
+    ];
+
+    for (i, (page_enum, title)) in pages.iter().enumerate() {
+        let is_sel = *page_enum == state.page;
+        let text = Span::styled(title.to_string(), if is_sel { sel_style } else { normal_style });
+        let mut block = Block::bordered();
+        if is_sel {
+            block = block.style(sel_style);
+        }
+        let p = Paragraph::new(text).alignment(Alignment::Center).block(block);
+        frame.render_widget(p, footer_chunks[i]);
+    }
+}
+
+// Download and parse the selected state when a page needs it
+async fn load_page(source: &Source, state: &mut AppState) {
+    if state.page == Page::States {
+        return;
+    }
+    let Some(idx) = state.state_key.as_ref().and_then(|k| state.states.iter().position(|s| s == k)) else {
+        state.page = Page::States;
+        return;
+    };
+    if matches!(&state.loaded, Some((loaded, _)) if *loaded == idx) {
+        return;
+    }
+    match source.read(&state.states[idx]).await {
+        Ok(tfstate) => {
+            state.loaded = Some((idx, tfstate));
+            state.message.clear();
+            state.module = None;
+            state.item = None;
+            state.idx = 0;
+            select(state);
+        }
+        Err(error) => {
+            state.message = format!("Failed to read {}: {}", state.states[idx], error);
+            state.loaded = None;
+            state.page = Page::States;
+            select(state);
+        }
+    }
+}
+
+async fn run_state(terminal: &mut ratatui::DefaultTerminal, source: &Source, states: Vec<String>) -> std::io::Result<()> {
+    let mut state = AppState {
+        states,
+        ..AppState::default()
+    };
+    select(&mut state);
+    // A single state is opened directly
+    if state.states.len() == 1 {
+        change_page(&mut state, Page::Modules);
+        load_page(source, &mut state).await;
+    }
+
+    loop {
+        terminal.draw(|frame| draw_state(frame, &state))?;
+        if handle_events(&mut state)? {
+            break Ok(());
+        }
+        load_page(source, &mut state).await;
+    }
+}
+
+pub async fn state(matches: &clap::ArgMatches) {
+    if matches.subcommand().is_some() {
+        return state_history(matches).await;
+    }
+    let (source, states) = match matches.get_one::<String>("file") {
+        Some(path) => (Source::File(path.clone()), vec![path.clone()]),
+        None => {
+            let bucket = arg_or_prompt(matches, "bucket", prompt_bucket_name);
+            let config = load_config(matches).await;
+            let client = S3Clients::new(&config).for_bucket(&bucket).await;
+            let states = match matches.get_one::<String>("key") {
+                Some(key) => vec![key.clone()],
+                None => match list_states(&client, &bucket).await {
+                    Ok((states, _)) => states,
+                    Err(error) => {
+                        println!("Failed to list the states of {}:\n {}", bucket, error);
+                        std::process::exit(1);
+                    }
+                },
+            };
+            (Source::Bucket { client, bucket }, states)
+        }
+    };
+    if states.is_empty() {
+        println!("No terraform state found");
+        return;
+    }
+
+    // Without a terminal, print the addresses like `terraform state list`
+    if !is_interactive() {
+        for key in &states {
+            match source.read(key).await {
+                Ok(tfstate) => {
+                    for resource in &tfstate.resources {
+                        println!("{}", resource.address);
+                    }
+                }
+                Err(error) => {
+                    println!("Failed to read {}: {}", key, error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    let mut terminal = ratatui::init();
+    let result = run_state(&mut terminal, &source, states).await;
+    ratatui::restore();
+    result.expect("Can't display the state");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const STATE: &str = r#"{
+        "version": 4,
+        "terraform_version": "1.9.5",
+        "serial": 12,
+        "lineage": "3f1c2a9e-0000-4000-8000-000000000000",
+        "outputs": {
+            "bucket": {"value": "my-bucket", "type": "string"},
+            "db_password": {"value": "hunter2", "type": "string", "sensitive": true}
+        },
+        "resources": [
+            {
+                "mode": "managed", "type": "aws_db_instance", "name": "main", "provider": "provider[\"registry.terraform.io/hashicorp/aws\"]",
+                "instances": [{
+                    "schema_version": 2,
+                    "attributes": {"identifier": "main", "password": "hunter2", "tags": {"env": "prod", "token": "abc"}, "ports": [5432, 6432]},
+                    "sensitive_attributes": [
+                        [{"type": "get_attr", "value": "password"}],
+                        [{"type": "get_attr", "value": "tags"}, {"type": "index", "value": {"value": "token", "type": "string"}}],
+                        [{"type": "get_attr", "value": "ports"}, {"type": "index", "value": {"value": 1, "type": "number"}}]
+                    ]
+                }]
+            },
+            {
+                "mode": "data", "type": "aws_ami", "name": "this",
+                "instances": [{"attributes": {"id": "ami-123"}, "sensitive_attributes": []}]
+            },
+            {
+                "module": "module.network", "mode": "managed", "type": "aws_subnet", "name": "private",
+                "instances": [
+                    {"index_key": 0, "attributes": {"id": "subnet-0"}},
+                    {"index_key": 1, "attributes": {"id": "subnet-1"}}
+                ]
+            },
+            {
+                "module": "module.app.module.dns", "mode": "managed", "type": "aws_route53_record", "name": "this",
+                "instances": [{"index_key": "api", "attributes": {"name": "api.example.com"}}]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_state_reads_resources_and_outputs() {
+        let state = parse_state(STATE).unwrap();
+        assert_eq!(state.serial, 12);
+        assert_eq!(state.lineage, "3f1c2a9e-0000-4000-8000-000000000000");
+        assert_eq!(state.terraform_version, "1.9.5");
+        assert_eq!(state.resources.len(), 5);
+        assert_eq!(state.outputs.keys().collect::<Vec<_>>(), vec!["bucket", "db_password"]);
+    }
+
+    #[test]
+    fn parse_state_rejects_other_versions() {
+        assert!(parse_state(r#"{"version": 3}"#).is_err());
+        assert!(parse_state("not json").is_err());
+    }
+
+    #[test]
+    fn resource_address_handles_modules_and_keys() {
+        let state = parse_state(STATE).unwrap();
+        let addresses: Vec<&str> = state.resources.iter().map(|r| r.address.as_str()).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                "aws_db_instance.main",
+                "data.aws_ami.this",
+                "module.network.aws_subnet.private[0]",
+                "module.network.aws_subnet.private[1]",
+                "module.app.module.dns.aws_route53_record.this[\"api\"]",
+            ]
+        );
+    }
+
+    #[test]
+    fn modules_lists_outputs_then_root_then_children() {
+        let state = parse_state(STATE).unwrap();
+        assert_eq!(state.modules(), vec![OUTPUTS, ROOT_MODULE, "module.app.module.dns", "module.network"]);
+        assert_eq!(state.items("module.network").len(), 2);
+    }
+
+    #[test]
+    fn detail_masks_sensitive_outputs_and_attributes() {
+        let state = parse_state(STATE).unwrap();
+        assert_eq!(state.detail(OUTPUTS, "db_password").unwrap()["value"], SENSITIVE);
+        assert_eq!(state.detail(OUTPUTS, "bucket").unwrap()["value"], "my-bucket");
+
+        let attributes = &state.detail(ROOT_MODULE, "aws_db_instance.main").unwrap()["attributes"];
+        assert_eq!(attributes["password"], SENSITIVE);
+        assert_eq!(attributes["tags"], json!({"env": "prod", "token": SENSITIVE}));
+        assert_eq!(attributes["ports"], json!([5432, SENSITIVE]));
+        assert_eq!(attributes["identifier"], "main");
+    }
+}
@@ -0,0 +1,610 @@
+use crate::commands::aws_utils::{error_message, load_config, load_config_in_region, S3Clients};
+use crate::commands::cli_utils::{arg_or_prompt, format_bytes, require_interactive};
+use crate::commands::config::{read_config, DeleteBucketConfig};
+use crate::commands::delete_bucket::{confirm_deletion, configured_protection, empty_bucket, ObjectFilter};
+use crate::commands::inti_aws_state::{bucket_exists, describe_table, prompt_bucket_name};
+use crate::commands::locks::list_locks;
+use crate::commands::state_history::list_state_versions;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::primitives::ByteStream;
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use promkit::preset::confirm::Confirm;
+use regex::Regex;
+
+// Buckets and tables of both sides of a migration, the tables are optional (lockfile locking)
+struct Backends {
+    from_bucket: String,
+    from_table: Option<String>,
+    to_bucket: String,
+    to_table: Option<String>,
+}
+
+// Returns the state keys and the S3 lockfiles (held locks) of the bucket
+pub(crate) async fn list_states(client: &aws_sdk_s3::Client, bucket_name: &str) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket_name)
+        .into_paginator()
+        .send();
+    let mut states = Vec::new();
+    let mut lockfiles = Vec::new();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|error| error_message(&error))?;
+        for object in page.contents() {
+            let key = object.key().unwrap_or_default();
+            if key.ends_with(".tfstate") {
+                states.push(key.to_string());
+            } else if key.ends_with(".tfstate.tflock") {
+                lockfiles.push(key.to_string());
+            }
+        }
+    }
+    Ok((states, lockfiles))
+}
+
+// Versions of a state, oldest first so the copies keep the same order. None is the current version
+async fn state_versions(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    key: &str,
+    all_versions: bool,
+) -> Result<Vec<Option<String>>, String> {
+    if !all_versions {
+        return Ok(vec![None]);
+    }
+    // Delete markers are skipped, a deleted state has nothing left to copy
+    let versions = list_state_versions(client, bucket_name, key).await?;
+    Ok(versions
+        .into_iter()
+        .rev()
+        .filter(|v| !v.deleted)
+        .map(|v| Some(v.version_id))
+        .collect())
+}
+
+// Download and upload instead of CopyObject, the buckets may live in different regions or accounts
+async fn copy_state(
+    from: &aws_sdk_s3::Client,
+    to: &aws_sdk_s3::Client,
+    backends: &Backends,
+    key: &str,
+    version_id: Option<String>,
+) -> Result<u64, String> {
+    let object = from
+        .get_object()
+        .bucket(&backends.from_bucket)
+        .key(key)
+        .set_version_id(version_id)
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    let body = object.body.collect().await.map_err(|error| error.to_string())?.into_bytes();
+    let size = body.len() as u64;
+    to.put_object()
+        .bucket(&backends.to_bucket)
+        .key(key)
+        .content_type("application/json")
+        .body(ByteStream::from(body))
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    Ok(size)
+}
+
+// Terraform checks the state against the digest stored in the lock table, a stale digest in
+// the new table would make every command fail
+async fn copy_digest(
+    from: &aws_sdk_dynamodb::Client,
+    to: &aws_sdk_dynamodb::Client,
+    backends: &Backends,
+    key: &str,
+) -> Result<bool, String> {
+    let (Some(from_table), Some(to_table)) = (&backends.from_table, &backends.to_table) else {
+        return Ok(false);
+    };
+    let from_id = format!("{}/{}-md5", backends.from_bucket, key);
+    let item = from
+        .get_item()
+        .table_name(from_table)
+        .key("LockID", AttributeValue::S(from_id))
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    let Some(mut item) = item.item else {
+        return Ok(false);
+    };
+    item.insert(
+        "LockID".to_string(),
+        AttributeValue::S(format!("{}/{}-md5", backends.to_bucket, key)),
+    );
+    to.put_item()
+        .table_name(to_table)
+        .set_item(Some(item))
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    Ok(true)
+}
+
+// Body of the `backend "s3" { ... }` block, other blocks of the file aren't touched
+fn backend_block(content: &str) -> Option<(usize, usize)> {
+    let start = Regex::new(r#"backend[ \t]+"s3"[ \t]*\{"#).unwrap().find(content)?.end();
+    let (mut depth, mut in_string, mut escaped) = (1, false, false);
+    for (i, c) in content[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((start, start + i));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Point a backend.tf of the source bucket to the destination, None when it uses another bucket
+fn rewrite_backend(content: &str, backends: &Backends, to_region: &str) -> Option<String> {
+    let (start, end) = backend_block(content)?;
+    let bucket = Regex::new(&format!(
+        r#"(?m)^([ \t]*)(bucket[ \t]*=[ \t]*)"{}""#,
+        regex::escape(&backends.from_bucket)
+    ))
+    .unwrap();
+    let indent = bucket.captures(&content[start..end])?[1].to_string();
+    let block = bucket.replace_all(&content[start..end], format!(r#"${{1}}${{2}}"{}""#, backends.to_bucket));
+    let region = Regex::new(r#"(?m)^([ \t]*region[ \t]*=[ \t]*)"[^"]*""#).unwrap();
+    let block = region.replace_all(&block, format!(r#"${{1}}"{}""#, to_region));
+
+    // The destination locks with its table only, or with a lockfile when it has none
+    let table = Regex::new(r#"(?m)^([ \t]*)dynamodb_table([ \t]*=[ \t]*)"[^"]*"[^\n]*\n?"#).unwrap();
+    let lockfile = Regex::new(r#"(?m)^[ \t]*use_lockfile[ \t]*=[^\n]*\n?"#).unwrap();
+    let (mut block, missing) = match &backends.to_table {
+        Some(to_table) if table.is_match(&block) => {
+            let block = table.replace_all(&block, format!("${{1}}dynamodb_table${{2}}\"{}\"\n", to_table));
+            (lockfile.replace_all(&block, "").into_owned(), None)
+        }
+        Some(to_table) => (
+            lockfile.replace_all(&block, "").into_owned(),
+            Some(format!("{}dynamodb_table = \"{}\"\n", indent, to_table)),
+        ),
+        None if lockfile.is_match(&block) => (table.replace_all(&block, "").into_owned(), None),
+        None if table.is_match(&block) => (table.replace_all(&block, "${1}use_lockfile   = true\n").into_owned(), None),
+        None => (block.into_owned(), Some(format!("{}use_lockfile   = true\n", indent))),
+    };
+    // A missing locking setting goes after the region, or the bucket
+    if let Some(line) = missing {
+        let anchor = region.find(&block).or_else(|| Regex::new(r#"(?m)^[ \t]*bucket[ \t]*="#).unwrap().find(&block));
+        let position = anchor.map_or(0, |anchor| match block[anchor.end()..].find('\n') {
+            Some(i) => anchor.end() + i + 1,
+            None => block.len(),
+        });
+        block.insert_str(position, &line);
+    }
+    Some(format!("{}{}{}", &content[..start], block, &content[end..]))
+}
+
+fn rewrite_backend_files(path: &str, backends: &Backends, to_region: &str) -> Vec<String> {
+    let pattern = format!("{}/**/backend.tf", path.trim_end_matches('/'));
+    let files = match glob::glob(&pattern) {
+        Ok(files) => files,
+        Err(error) => {
+            println!("Invalid path {}: {}", path, error);
+            return Vec::new();
+        }
+    };
+    let mut rewritten = Vec::new();
+    for file in files.flatten() {
+        // Modules downloaded by terraform aren't part of the repository
+        if file.components().any(|c| c.as_os_str() == ".terraform") {
+            continue;
+        }
+        let Ok(content) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        if let Some(content) = rewrite_backend(&content, backends, to_region) {
+            match std::fs::write(&file, content) {
+                Ok(_) => rewritten.push(file.display().to_string()),
+                Err(error) => println!("Failed to rewrite {}: {}", file.display(), error),
+            }
+        }
+    }
+    rewritten
+}
+
+fn plan_table(backends: &Backends, states: usize, versions: usize) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["", "From", "To"])
+        .add_row(vec!["Bucket", backends.from_bucket.as_str(), backends.to_bucket.as_str()])
+        .add_row(vec![
+            "Lock table",
+            backends.from_table.as_deref().unwrap_or("-"),
+            backends.to_table.as_deref().unwrap_or("-"),
+        ])
+        .add_row(vec!["States".to_string(), states.to_string(), String::new()])
+        .add_row(vec!["Versions".to_string(), versions.to_string(), String::new()]);
+    table
+}
+
+fn confirm(message: &str) -> bool {
+    let mut confirm = Confirm::new(message).prompt().unwrap();
+    let confirm_string = match confirm.run() {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    confirm_string == "yes" || confirm_string == "y"
+}
+
+async fn migrate(matches: &clap::ArgMatches) {
+    let backends = Backends {
+        from_bucket: arg_or_prompt(matches, "from-bucket", prompt_bucket_name),
+        from_table: matches.get_one::<String>("from-table").cloned(),
+        to_bucket: arg_or_prompt(matches, "to-bucket", prompt_bucket_name),
+        to_table: matches.get_one::<String>("to-table").cloned(),
+    };
+    if backends.from_bucket == backends.to_bucket {
+        println!("The source and destination buckets are the same");
+        std::process::exit(1);
+    }
+
+    let config = load_config(matches).await;
+    let clients = S3Clients::new(&config);
+    match bucket_exists(&clients.default_client(), &backends.to_bucket).await {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Bucket {} doesn't exist, create it with init-aws-state first", backends.to_bucket);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to check S3 bucket {}:\n {}", backends.to_bucket, error);
+            std::process::exit(1);
+        }
+    }
+    let from_region = clients.bucket_region(&backends.from_bucket).await.unwrap_or_default();
+    let to_region = clients.bucket_region(&backends.to_bucket).await.unwrap_or_default();
+    let from_s3 = clients.for_bucket(&backends.from_bucket).await;
+    let to_s3 = clients.for_bucket(&backends.to_bucket).await;
+    // Each lock table lives next to its bucket
+    let from_dynamo = aws_sdk_dynamodb::Client::new(&load_config_in_region(matches, Some(&from_region)).await);
+    let to_dynamo = aws_sdk_dynamodb::Client::new(&load_config_in_region(matches, Some(&to_region)).await);
+
+    let (states, lockfiles) = match list_states(&from_s3, &backends.from_bucket).await {
+        Ok(listing) => listing,
+        Err(error) => {
+            println!("Failed to list the states of {}:\n {}", backends.from_bucket, error);
+            std::process::exit(1);
+        }
+    };
+    if states.is_empty() {
+        println!("No terraform state found in {}", backends.from_bucket);
+        return;
+    }
+
+    // Copying a state while terraform writes it would lose the write
+    let mut locks = lockfiles;
+    if let Some(from_table) = &backends.from_table {
+        match list_locks(&from_dynamo, from_table).await {
+            Ok(table_locks) => locks.extend(table_locks.into_iter().map(|l| l.lock_id)),
+            Err(error) => {
+                println!("Failed to read the locks of {}:\n {}", from_table, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if !locks.is_empty() {
+        println!("{} some states are locked, wait for terraform to finish:", "Refusing:".red().bold());
+        for lock in &locks {
+            println!("  - {}", lock);
+        }
+        std::process::exit(1);
+    }
+    if let Some(to_table) = &backends.to_table {
+        if !matches!(describe_table(&to_dynamo, to_table).await, Ok(Some(_))) {
+            println!("DynamoDB table {} doesn't exist, create it with init-aws-state first", to_table);
+            std::process::exit(1);
+        }
+    }
+
+    let existing = match list_states(&to_s3, &backends.to_bucket).await {
+        Ok((existing, _)) => existing,
+        Err(error) => {
+            println!("Failed to list the states of {}:\n {}", backends.to_bucket, error);
+            std::process::exit(1);
+        }
+    };
+    let conflicts: Vec<&String> = states.iter().filter(|s| existing.contains(s)).collect();
+    if !conflicts.is_empty() && !matches.get_flag("overwrite") {
+        println!("{} these states already exist in {}:", "Refusing:".red().bold(), backends.to_bucket);
+        for conflict in &conflicts {
+            println!("  - {}", conflict);
+        }
+        println!("Run again with --overwrite to replace them");
+        std::process::exit(1);
+    }
+
+    let all_versions = matches.get_flag("versions");
+    let mut plan: Vec<(String, Vec<Option<String>>)> = Vec::new();
+    for key in states {
+        match state_versions(&from_s3, &backends.from_bucket, &key, all_versions).await {
+            Ok(versions) => plan.push((key, versions)),
+            Err(error) => {
+                println!("Failed to list the versions of {}:\n {}", key, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    let versions: usize = plan.iter().map(|(_, v)| v.len()).sum();
+    println!("{}", plan_table(&backends, plan.len(), versions));
+    if !matches.get_flag("yes") {
+        require_interactive("yes");
+        if !confirm("Do you want to copy these states ?") {
+            println!("Aborted by user");
+            std::process::exit(1);
+        }
+    }
+
+    let mut errors = 0;
+    let mut bytes = 0;
+    let mut digests = 0;
+    for (key, versions) in &plan {
+        for version_id in versions {
+            match copy_state(&from_s3, &to_s3, &backends, key, version_id.clone()).await {
+                Ok(size) => bytes += size,
+                Err(error) => {
+                    println!("Failed to copy {}:\n {}", key, error);
+                    errors += 1;
+                }
+            }
+        }
+        match copy_digest(&from_dynamo, &to_dynamo, &backends, key).await {
+            Ok(true) => digests += 1,
+            Ok(false) => {}
+            Err(error) => {
+                println!("Failed to copy the digest of {}:\n {}", key, error);
+                errors += 1;
+            }
+        }
+        println!("Copied {}", key);
+    }
+    println!(
+        "{} versions of {} states copied ({}), {} digests",
+        versions,
+        plan.len(),
+        format_bytes(bytes),
+        digests
+    );
+    if errors > 0 {
+        println!("{} copies failed, backend.tf files are left untouched", errors);
+        std::process::exit(1);
+    }
+
+    if let Some(path) = matches.get_one::<String>("path") {
+        let rewritten = rewrite_backend_files(path, &backends, &to_region);
+        for file in &rewritten {
+            println!("Rewrote {}", file);
+        }
+        println!("{} backend.tf files point to {}", rewritten.len(), backends.to_bucket);
+    }
+    println!(
+        "States migrated {}, run {} in each environment",
+        "successfully".green().bold(),
+        "terraform init -reconfigure".bold()
+    );
+}
+
+// Why the backend can't be destroyed. Unlike delete-bucket, the versions of old states don't
+// protect it: they are history, a current object of any kind means the bucket is still used
+async fn destroy_refusal(client: &aws_sdk_s3::Client, bucket_name: &str, config: &DeleteBucketConfig) -> Option<String> {
+    if let Some(reason) = configured_protection(client, bucket_name, config).await {
+        return Some(format!("{} refusing to destroy {}: {}", "Protected:".red().bold(), bucket_name.bold(), reason));
+    }
+    match client.list_objects_v2().bucket(bucket_name).max_keys(1).send().await {
+        Ok(listing) if !listing.contents().is_empty() => {
+            let key = listing.contents()[0].key().unwrap_or_default();
+            Some(format!("{} {} isn't empty, it still holds {}", "Refusing:".red().bold(), bucket_name.bold(), key))
+        }
+        Ok(_) => None,
+        Err(error) => Some(format!("Failed to list the objects of {}:\n {}", bucket_name, error_message(&error))),
+    }
+}
+
+async fn destroy(matches: &clap::ArgMatches) {
+    let bucket_name = arg_or_prompt(matches, "bucket", prompt_bucket_name);
+    let table_name = matches.get_one::<String>("table");
+
+    let config = load_config(matches).await;
+    let clients = S3Clients::new(&config);
+    let client = clients.for_bucket(&bucket_name).await;
+    let region = clients.bucket_region(&bucket_name).await.unwrap_or_default();
+    let dynamo = aws_sdk_dynamodb::Client::new(&load_config_in_region(matches, Some(&region)).await);
+
+    if let Some(refusal) = destroy_refusal(&client, &bucket_name, &read_config().delete_bucket).await {
+        println!("{}", refusal);
+        std::process::exit(1);
+    }
+    if let Some(table_name) = table_name {
+        match list_locks(&dynamo, table_name).await {
+            Ok(locks) if !locks.is_empty() => {
+                println!("{} {} still holds {} locks", "Refusing:".red().bold(), table_name.bold(), locks.len());
+                std::process::exit(1);
+            }
+            Ok(_) => {}
+            Err(error) => {
+                println!("Failed to read the locks of {}:\n {}", table_name, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if !matches.get_flag("yes") {
+        require_interactive("yes");
+        if !confirm_deletion(std::slice::from_ref(&bucket_name), &ObjectFilter::default()) {
+            println!("Aborted by user");
+            std::process::exit(1);
+        }
+    }
+
+    let report = empty_bucket(&client, &bucket_name, &ObjectFilter::default(), 16, true).await;
+    if !report.success() {
+        println!("Failed to remove the old versions of {} ({} errors)", bucket_name, report.errors);
+        std::process::exit(1);
+    }
+    if let Err(error) = client.delete_bucket().bucket(&bucket_name).send().await {
+        println!("Failed to delete S3 bucket {}:\n {}", bucket_name, error_message(&error));
+        std::process::exit(1);
+    }
+    println!("S3 bucket {} deleted", bucket_name);
+    if let Some(table_name) = table_name {
+        if let Err(error) = dynamo.delete_table().table_name(table_name).send().await {
+            println!("Failed to delete dynamoDB table {}:\n {}", table_name, error_message(&error));
+            std::process::exit(1);
+        }
+        println!("DynamoDB table {} deleted", table_name);
+    }
+    println!("State backend destroyed {}", "successfully".green().bold());
+}
+
+pub async fn state_backend(matches: &clap::ArgMatches) {
+    match matches.subcommand() {
+        Some(("migrate", sub_matches)) => migrate(sub_matches).await,
+        Some(("destroy", sub_matches)) => destroy(sub_matches).await,
+        _ => println!("No valid subcommand was used, please use the --help flag for more information"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::aws_stub::AwsStub;
+    use crate::commands::delete_bucket::protection_reason;
+
+    const NO_TAGS: &str = "<Error><Code>NoSuchTagSet</Code><Message>The TagSet does not exist</Message></Error>";
+
+    // Versioned backend whose only state was deleted: an old version and a delete marker remain
+    async fn retired_backend(current: &'static str) -> AwsStub {
+        AwsStub::start(move |request| {
+            if request.uri.contains("tagging") {
+                (404, NO_TAGS.to_string())
+            } else if request.uri.contains("versions") {
+                (
+                    200,
+                    "<ListVersionsResult><Name>states</Name><IsTruncated>false</IsTruncated>\
+                     <Version><Key>prod/terraform.tfstate</Key><VersionId>v1</VersionId><IsLatest>false</IsLatest>\
+                     <LastModified>2024-01-01T00:00:00.000Z</LastModified><Size>10</Size></Version>\
+                     <DeleteMarker><Key>prod/terraform.tfstate</Key><VersionId>v2</VersionId><IsLatest>true</IsLatest>\
+                     <LastModified>2024-02-01T00:00:00.000Z</LastModified></DeleteMarker></ListVersionsResult>"
+                        .to_string(),
+                )
+            } else {
+                (200, format!("<ListBucketResult><Name>states</Name><IsTruncated>false</IsTruncated>{}</ListBucketResult>", current))
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn destroy_accepts_old_state_versions() {
+        let stub = retired_backend("").await;
+        let config = DeleteBucketConfig::default();
+        assert!(destroy_refusal(&stub.s3(), "states", &config).await.is_none());
+        assert!(!stub.requests().iter().any(|r| r.contains("versions")));
+        // delete-bucket keeps refusing it, the versions are still states
+        let reason = protection_reason(&stub.s3(), "states", &config, false).await.unwrap();
+        assert!(reason.contains("terraform state"));
+    }
+
+    #[tokio::test]
+    async fn destroy_refuses_current_objects_and_protection() {
+        let stub = retired_backend("<Contents><Key>prod/terraform.tfstate</Key><Size>10</Size></Contents>").await;
+        let refusal = destroy_refusal(&stub.s3(), "states", &DeleteBucketConfig::default()).await.unwrap();
+        assert!(refusal.contains("prod/terraform.tfstate"));
+
+        let config = DeleteBucketConfig { protected_patterns: vec!["sta*".to_string()], ..Default::default() };
+        let refusal = destroy_refusal(&stub.s3(), "states", &config).await.unwrap();
+        assert!(refusal.contains("protected pattern sta*"));
+    }
+
+    fn backends(from_table: Option<&str>, to_table: Option<&str>) -> Backends {
+        Backends {
+            from_bucket: "old-states".to_string(),
+            from_table: from_table.map(|t| t.to_string()),
+            to_bucket: "new-states".to_string(),
+            to_table: to_table.map(|t| t.to_string()),
+        }
+    }
+
+    fn backend_file(locking: &str) -> String {
+        format!(
+            "terraform {{\n    backend \"s3\" {{\n        bucket = \"old-states\"\n        key    = \"prod/terraform.tfstate\"\n        region = \"eu-west-1\"\n{}        encrypt        = true\n    }}\n}}\n",
+            locking
+        )
+    }
+
+    const TABLE: &str = "        dynamodb_table = \"old-locks\"\n";
+    const LOCKFILE: &str = "        use_lockfile   = true\n";
+
+    fn expected(locking: &str) -> String {
+        backend_file(locking).replace("old-states", "new-states").replace("eu-west-1", "eu-central-1")
+    }
+
+    #[test]
+    fn rewrite_table_to_table() {
+        let content = rewrite_backend(&backend_file(TABLE), &backends(Some("old-locks"), Some("new-locks")), "eu-central-1");
+        assert_eq!(content.unwrap(), expected("        dynamodb_table = \"new-locks\"\n"));
+    }
+
+    #[test]
+    fn rewrite_table_to_lockfile() {
+        let content = rewrite_backend(&backend_file(TABLE), &backends(Some("old-locks"), None), "eu-central-1");
+        assert_eq!(content.unwrap(), expected(LOCKFILE));
+    }
+
+    #[test]
+    fn rewrite_lockfile_to_table() {
+        let content = rewrite_backend(&backend_file(LOCKFILE), &backends(None, Some("new-locks")), "eu-central-1");
+        assert_eq!(content.unwrap(), expected("        dynamodb_table = \"new-locks\"\n"));
+    }
+
+    #[test]
+    fn rewrite_both_to_table_drops_the_lockfile() {
+        let both = format!("{}{}", TABLE, LOCKFILE);
+        let content = rewrite_backend(&backend_file(&both), &backends(Some("old-locks"), Some("new-locks")), "eu-central-1");
+        assert_eq!(content.unwrap(), expected("        dynamodb_table = \"new-locks\"\n"));
+    }
+
+    #[test]
+    fn rewrite_lockfile_to_lockfile() {
+        let content = rewrite_backend(&backend_file(LOCKFILE), &backends(None, None), "eu-central-1");
+        assert_eq!(content.unwrap(), expected(LOCKFILE));
+    }
+
+    #[test]
+    fn rewrite_keeps_other_blocks() {
+        let provider = "provider \"aws\" {\n  region = \"us-east-1\"\n}\n\nresource \"aws_s3_bucket\" \"b\" {\n  bucket = \"old-states\"\n}\n";
+        let content = format!("{}{}", provider, backend_file(TABLE));
+        let rewritten = rewrite_backend(&content, &backends(Some("old-locks"), Some("new-locks")), "eu-central-1").unwrap();
+        assert_eq!(rewritten, format!("{}{}", provider, expected("        dynamodb_table = \"new-locks\"\n")));
+    }
+
+    #[test]
+    fn rewrite_skips_other_buckets() {
+        let content = backend_file(TABLE).replace("old-states", "other-states");
+        assert!(rewrite_backend(&content, &backends(Some("old-locks"), None), "eu-central-1").is_none());
+        assert!(rewrite_backend("provider \"aws\" {}\n", &backends(None, None), "eu-central-1").is_none());
+    }
+}
@@ -0,0 +1,694 @@
+use crate::commands::aws_utils::{error_message, load_config, load_config_in_region, S3Clients};
+use crate::commands::cli_utils::{arg_or_prompt, format_bytes, require_interactive};
+use crate::commands::inti_aws_state::prompt_bucket_name;
+use crate::commands::state::{download_state, parse_state, sensitive_paths, PathStep, TerraformState, SENSITIVE};
+use crate::commands::state_backend::list_states;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::primitives::{ByteStream, DateTime, DateTimeFormat};
+use aws_sdk_s3::types::BucketVersioningStatus;
+use colored::Colorize;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use promkit::preset::listbox::Listbox;
+use promkit::preset::readline::Readline;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Longer attribute values are cut in the diff
+const MAX_VALUE_LENGTH: usize = 60;
+
+pub(crate) struct StateVersion {
+    pub(crate) version_id: String,
+    pub(crate) last_modified: Option<DateTime>,
+    pub(crate) size: i64,
+    pub(crate) is_latest: bool,
+    pub(crate) deleted: bool,
+}
+
+// Versions and delete markers of a state, newest first
+pub(crate) async fn list_state_versions(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    key: &str,
+) -> Result<Vec<StateVersion>, String> {
+    let mut versions = Vec::new();
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+    loop {
+        let page = client
+            .list_object_versions()
+            .bucket(bucket_name)
+            .prefix(key)
+            .set_key_marker(key_marker)
+            .set_version_id_marker(version_id_marker)
+            .send()
+            .await
+            .map_err(|error| error_message(&error))?;
+        for version in page.versions().iter().filter(|v| v.key() == Some(key)) {
+            versions.push(StateVersion {
+                version_id: version.version_id().unwrap_or("null").to_string(),
+                last_modified: version.last_modified().cloned(),
+                size: version.size().unwrap_or_default(),
+                is_latest: version.is_latest().unwrap_or(false),
+                deleted: false,
+            });
+        }
+        for marker in page.delete_markers().iter().filter(|m| m.key() == Some(key)) {
+            versions.push(StateVersion {
+                version_id: marker.version_id().unwrap_or("null").to_string(),
+                last_modified: marker.last_modified().cloned(),
+                size: 0,
+                is_latest: marker.is_latest().unwrap_or(false),
+                deleted: true,
+            });
+        }
+        if !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().map(|m| m.to_string());
+        version_id_marker = page.next_version_id_marker().map(|m| m.to_string());
+    }
+    versions.sort_by(|a, b| b.last_modified.map(|d| d.as_nanos()).cmp(&a.last_modified.map(|d| d.as_nanos())));
+    Ok(versions)
+}
+
+fn format_date(date: Option<&DateTime>) -> String {
+    date.and_then(|d| d.fmt(DateTimeFormat::DateTime).ok()).unwrap_or_default()
+}
+
+fn prompt_state_key(states: &[String]) -> String {
+    Listbox::new(states)
+        .title("Which state do you want?")
+        .listbox_lines(10)
+        .prompt()
+        .unwrap()
+        .run()
+        .unwrap()
+}
+
+// --key, the only state of the bucket, or a prompt
+async fn state_key(client: &aws_sdk_s3::Client, bucket_name: &str, matches: &clap::ArgMatches) -> String {
+    if let Some(key) = matches.get_one::<String>("key") {
+        return key.clone();
+    }
+    let states = match list_states(client, bucket_name).await {
+        Ok((states, _)) => states,
+        Err(error) => {
+            println!("Failed to list the states of {}:\n {}", bucket_name, error);
+            std::process::exit(1);
+        }
+    };
+    match states.len() {
+        0 => {
+            println!("No terraform state found in {}", bucket_name);
+            std::process::exit(1);
+        }
+        1 => states[0].clone(),
+        _ => {
+            require_interactive("key");
+            prompt_state_key(&states)
+        }
+    }
+}
+
+async fn bucket_client(matches: &clap::ArgMatches) -> (aws_sdk_s3::Client, String) {
+    let bucket = arg_or_prompt(matches, "bucket", prompt_bucket_name);
+    let config = load_config(matches).await;
+    let client = S3Clients::new(&config).for_bucket(&bucket).await;
+    (client, bucket)
+}
+
+fn is_local(version: &str) -> bool {
+    std::path::Path::new(version).is_file()
+}
+
+// A version ID of the state in the bucket, "latest", or a local state file
+async fn read_version(remote: Option<(&aws_sdk_s3::Client, &str, &str)>, version: &str) -> TerraformState {
+    let content = match remote {
+        _ if is_local(version) => std::fs::read_to_string(version).map_err(|error| error.to_string()),
+        Some((client, bucket_name, key)) => {
+            let version_id = (version != "latest").then(|| version.to_string());
+            download_state(client, bucket_name, key, version_id).await
+        }
+        None => Err("no such file".to_string()),
+    };
+    match content.and_then(|c| parse_state(&c)) {
+        Ok(tfstate) => tfstate,
+        Err(error) => {
+            println!("Failed to read {}:\n {}", version, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn history(matches: &clap::ArgMatches) {
+    let (client, bucket) = bucket_client(matches).await;
+    let key = state_key(&client, &bucket, matches).await;
+    let limit = *matches.get_one::<usize>("limit").unwrap();
+    let versions = match list_state_versions(&client, &bucket, &key).await {
+        Ok(versions) => versions,
+        Err(error) => {
+            println!("Failed to list the versions of {}:\n {}", key, error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Version", "Last modified", "Size", "Serial", "Resources", ""]);
+    for version in versions.iter().take(limit) {
+        // Every version is downloaded to read its serial, states are small
+        let (serial, resources) = if version.deleted {
+            ("-".to_string(), "-".to_string())
+        } else {
+            match download_state(&client, &bucket, &key, Some(version.version_id.clone()))
+                .await
+                .and_then(|c| parse_state(&c))
+            {
+                Ok(tfstate) => (tfstate.serial.to_string(), tfstate.resources.len().to_string()),
+                Err(error) => (format!("unreadable: {}", error), "-".to_string()),
+            }
+        };
+        let status = match (version.is_latest, version.deleted) {
+            (true, true) => "current (deleted)",
+            (true, false) => "current",
+            (false, true) => "deleted",
+            (false, false) => "",
+        };
+        table.add_row(vec![
+            version.version_id.clone(),
+            format_date(version.last_modified.as_ref()),
+            format_bytes(version.size.max(0) as u64),
+            serial,
+            resources,
+            status.to_string(),
+        ]);
+    }
+    println!("History of {}", key.bold());
+    println!("{}", table);
+    if versions.len() > limit {
+        println!("{} older versions not shown, use --limit to see them", versions.len() - limit);
+    }
+}
+
+// {"a": {"b": [1]}} -> a.b[0] = 1
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&path, value, out);
+            }
+        }
+        Value::Array(values) if !values.is_empty() => {
+            for (index, value) in values.iter().enumerate() {
+                flatten(&format!("{}[{}]", prefix, index), value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn attributes(value: &Value) -> BTreeMap<String, Value> {
+    let mut out = BTreeMap::new();
+    if let Some(attributes) = value.get("attributes") {
+        flatten("", attributes, &mut out);
+    }
+    out
+}
+
+fn short(value: Option<&Value>) -> String {
+    let text = match value {
+        Some(value) => value.to_string(),
+        None => "(none)".to_string(),
+    };
+    if text.chars().count() > MAX_VALUE_LENGTH {
+        format!("{}...", text.chars().take(MAX_VALUE_LENGTH).collect::<String>())
+    } else {
+        text
+    }
+}
+
+// Sensitive attributes of an instance, as paths of flatten
+fn flat_sensitive_paths(value: &Value) -> Vec<String> {
+    sensitive_paths(value)
+        .into_iter()
+        .map(|steps| {
+            let mut path = String::new();
+            for step in steps {
+                match step {
+                    PathStep::Key(key) if path.is_empty() => path = key,
+                    PathStep::Key(key) => path = format!("{}.{}", path, key),
+                    PathStep::Index(index) => path = format!("{}[{}]", path, index),
+                }
+            }
+            path
+        })
+        .collect()
+}
+
+// The path itself or anything under it is sensitive
+fn is_sensitive(path: &str, sensitive: &[String]) -> bool {
+    sensitive.iter().any(|s| {
+        path == s || path.strip_prefix(s.as_str()).is_some_and(|rest| rest.starts_with('.') || rest.starts_with('['))
+    })
+}
+
+// Resource by resource, like a terraform plan: + added, - removed, ~ changed attributes
+fn diff_lines(from: &TerraformState, to: &TerraformState) -> Vec<String> {
+    let from_resources: BTreeMap<&str, &Value> = from.resources.iter().map(|r| (r.address.as_str(), &r.value)).collect();
+    let to_resources: BTreeMap<&str, &Value> = to.resources.iter().map(|r| (r.address.as_str(), &r.value)).collect();
+    let mut addresses: Vec<&str> = from_resources.keys().chain(to_resources.keys()).copied().collect();
+    addresses.sort();
+    addresses.dedup();
+
+    let mut lines = Vec::new();
+    for address in addresses {
+        match (from_resources.get(address), to_resources.get(address)) {
+            (None, Some(_)) => lines.push(format!("+ {}", address)),
+            (Some(_), None) => lines.push(format!("- {}", address)),
+            (Some(before), Some(after)) => {
+                let mut sensitive = flat_sensitive_paths(before);
+                sensitive.extend(flat_sensitive_paths(after));
+                let before = attributes(before);
+                let after = attributes(after);
+                let mut paths: Vec<&String> = before.keys().chain(after.keys()).collect();
+                paths.sort();
+                paths.dedup();
+                let changed: Vec<&String> = paths.into_iter().filter(|p| before.get(*p) != after.get(*p)).collect();
+                if changed.is_empty() {
+                    continue;
+                }
+                lines.push(format!("~ {}", address));
+                for path in changed {
+                    if is_sensitive(path, &sensitive) {
+                        lines.push(format!("    {} {}", path, SENSITIVE));
+                    } else {
+                        lines.push(format!("    {}: {} -> {}", path, short(before.get(path)), short(after.get(path))));
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    let mut names: Vec<&String> = from.outputs.keys().chain(to.outputs.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let before = from.outputs.get(name).and_then(|o| o.get("value"));
+        let after = to.outputs.get(name).and_then(|o| o.get("value"));
+        if before == after {
+            continue;
+        }
+        let sensitive = [from.outputs.get(name), to.outputs.get(name)]
+            .iter()
+            .flatten()
+            .any(|o| o.get("sensitive").and_then(|s| s.as_bool()).unwrap_or(false));
+        if sensitive {
+            lines.push(format!("~ output.{} {}", name, SENSITIVE));
+        } else {
+            lines.push(format!("~ output.{}: {} -> {}", name, short(before), short(after)));
+        }
+    }
+    lines
+}
+
+// Returns the number of changed resources and outputs
+fn print_diff(from: &TerraformState, to: &TerraformState) -> usize {
+    let mut changes = 0;
+    for line in diff_lines(from, to) {
+        match line.chars().next() {
+            Some('+') => println!("{}", line.green()),
+            Some('-') => println!("{}", line.red()),
+            Some('~') => println!("{}", line.yellow()),
+            _ => {
+                println!("{}", line);
+                continue;
+            }
+        }
+        changes += 1;
+    }
+    changes
+}
+
+async fn diff(matches: &clap::ArgMatches) {
+    let from_version = matches.get_one::<String>("from").unwrap();
+    let to_version = matches.get_one::<String>("to").unwrap();
+    // Two local files are compared offline
+    let (from, to, key) = if is_local(from_version) && is_local(to_version) {
+        let from = read_version(None, from_version).await;
+        let to = read_version(None, to_version).await;
+        (from, to, "local states".to_string())
+    } else {
+        let (client, bucket) = bucket_client(matches).await;
+        let key = state_key(&client, &bucket, matches).await;
+        let remote = Some((&client, bucket.as_str(), key.as_str()));
+        let from = read_version(remote, from_version).await;
+        let to = read_version(remote, to_version).await;
+        (from, to, key)
+    };
+
+    println!(
+        "Diff of {} from serial {} ({}) to serial {} ({})",
+        key.bold(),
+        from.serial,
+        from_version,
+        to.serial,
+        to_version
+    );
+    if from.lineage != to.lineage {
+        println!("{} the versions have different lineages, they belong to different states", "Warning:".yellow().bold());
+    }
+    if print_diff(&from, &to) == 0 {
+        println!("No changes");
+    }
+}
+
+// Terraform doesn't take a lock to read, but a running apply would overwrite the restored state
+async fn lock_holder(
+    client: &aws_sdk_s3::Client,
+    bucket_name: &str,
+    key: &str,
+    dynamo: Option<(&aws_sdk_dynamodb::Client, &str)>,
+) -> Result<Option<String>, String> {
+    let lockfile = format!("{}.tflock", key);
+    match client.head_object().bucket(bucket_name).key(&lockfile).send().await {
+        Ok(_) => return Ok(Some(format!("lockfile {}", lockfile))),
+        Err(error) if error.raw_response().map(|r| r.status().as_u16()) == Some(404) => {}
+        Err(error) => return Err(error_message(&error)),
+    }
+    let Some((dynamo, table)) = dynamo else {
+        return Ok(None);
+    };
+    let lock_id = format!("{}/{}", bucket_name, key);
+    let item = dynamo
+        .get_item()
+        .table_name(table)
+        .key("LockID", AttributeValue::S(lock_id.clone()))
+        .send()
+        .await
+        .map_err(|error| error_message(&error))?;
+    match item.item {
+        Some(item) if item.contains_key("Info") => Ok(Some(format!("lock {} of {}", lock_id, table))),
+        _ => Ok(None),
+    }
+}
+
+fn confirm_restore(key: &str, version_id: &str, serial: u64) -> bool {
+    let mut confirm = Readline::default()
+        .title(format!(
+            "This will replace the current state of {} by version {} (serial {}). Type the state key to confirm",
+            key, version_id, serial
+        ))
+        .prompt()
+        .unwrap();
+    let confirm_string = match confirm.run() {
+        Ok(value) => value,
+        Err(_) => {
+            print!("Aborted by user");
+            std::process::exit(1);
+        }
+    };
+    confirm_string.trim() == key
+}
+
+// Lock tables of the region holding a digest of the state, terraform compares the state to it
+async fn digest_tables(dynamo: &aws_sdk_dynamodb::Client, bucket: &str, key: &str) -> Result<Vec<String>, String> {
+    let tables = dynamo
+        .list_tables()
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(|error| error_message(&error))?;
+    let id = format!("{}/{}-md5", bucket, key);
+    let mut found = Vec::new();
+    for table in tables {
+        let item = dynamo.get_item().table_name(&table).key("LockID", AttributeValue::S(id.clone())).send().await;
+        // Tables of other applications don't have a LockID key, the lookup fails
+        if item.is_ok_and(|output| output.item.is_some()) {
+            found.push(table);
+        }
+    }
+    Ok(found)
+}
+
+async fn restore(matches: &clap::ArgMatches) {
+    let (client, bucket) = bucket_client(matches).await;
+    let key = state_key(&client, &bucket, matches).await;
+    let version_id = matches.get_one::<String>("version").unwrap();
+    let table = matches.get_one::<String>("table");
+    // The lock table lives in the region of the bucket
+    let config = load_config(matches).await;
+    let dynamo = match S3Clients::new(&config).bucket_region(&bucket).await {
+        Some(region) => aws_sdk_dynamodb::Client::new(&load_config_in_region(matches, Some(&region)).await),
+        None => aws_sdk_dynamodb::Client::new(&config),
+    };
+
+    // Without versioning, the current state would be lost for good
+    match client.get_bucket_versioning().bucket(&bucket).send().await {
+        Ok(versioning) if versioning.status() == Some(&BucketVersioningStatus::Enabled) => {}
+        Ok(_) => {
+            println!("{} versioning isn't enabled on {}", "Refusing:".red().bold(), bucket);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to read the versioning of {}:\n {}", bucket, error_message(&error));
+            std::process::exit(1);
+        }
+    }
+    match lock_holder(&client, &bucket, &key, table.map(|t| (&dynamo, t.as_str()))).await {
+        Ok(None) => {}
+        Ok(Some(lock)) => {
+            println!("{} the state is locked by {}, wait for terraform to finish", "Refusing:".red().bold(), lock);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to check the locks of {}:\n {}", key, error);
+            std::process::exit(1);
+        }
+    }
+
+    if table.is_none() {
+        match digest_tables(&dynamo, &bucket, &key).await {
+            Ok(tables) if !tables.is_empty() => {
+                println!(
+                    "{} {} keeps a digest of {}, pass --table {} so that terraform can still read the restored state",
+                    "Refusing:".red().bold(),
+                    tables.join(", "),
+                    key,
+                    tables[0]
+                );
+                std::process::exit(1);
+            }
+            Ok(_) => {}
+            Err(error) => println!(
+                "{} couldn't look for a lock table digest of {} ({}), pass --table if the backend uses one",
+                "Warning:".yellow().bold(),
+                key,
+                error
+            ),
+        }
+    }
+
+    let current = read_version(Some((&client, bucket.as_str(), key.as_str())), "latest").await;
+    let content = match download_state(&client, &bucket, &key, Some(version_id.clone())).await {
+        Ok(content) => content,
+        Err(error) => {
+            println!("Failed to read version {} of {}:\n {}", version_id, key, error);
+            std::process::exit(1);
+        }
+    };
+    let restored = match parse_state(&content) {
+        Ok(restored) => restored,
+        Err(error) => {
+            println!("Version {} of {} isn't a valid state: {}", version_id, key, error);
+            std::process::exit(1);
+        }
+    };
+    if restored.lineage != current.lineage {
+        println!(
+            "{} version {} has lineage {}, the current state has {}",
+            "Refusing:".red().bold(),
+            version_id,
+            restored.lineage,
+            current.lineage
+        );
+        std::process::exit(1);
+    }
+
+    println!("Changes from the current state (serial {}) to version {}:", current.serial, version_id);
+    if print_diff(&current, &restored) == 0 {
+        println!("No changes");
+    }
+    if !matches.get_flag("yes") {
+        require_interactive("yes");
+        if !confirm_restore(&key, version_id, restored.serial) {
+            println!("Aborted by user");
+            std::process::exit(1);
+        }
+    }
+
+    // Terraform refuses states older than the one it last saw, the restored state goes on top
+    let mut state: Value = serde_json::from_str(&content).unwrap();
+    let serial = current.serial + 1;
+    state["serial"] = Value::from(serial);
+    let body = serde_json::to_string_pretty(&state).unwrap() + "\n";
+    let digest = format!("{:x}", md5::compute(body.as_bytes()));
+    let output = match client
+        .put_object()
+        .bucket(&bucket)
+        .key(&key)
+        .content_type("application/json")
+        .body(ByteStream::from(body.into_bytes()))
+        .send()
+        .await
+    {
+        Ok(output) => output,
+        Err(error) => {
+            println!("Failed to restore {}:\n {}", key, error_message(&error));
+            std::process::exit(1);
+        }
+    };
+
+    // The digest of the lock table has to match, or terraform refuses to read the state
+    if let Some(table) = table {
+        let result = dynamo
+            .put_item()
+            .table_name(table)
+            .item("LockID", AttributeValue::S(format!("{}/{}-md5", bucket, key)))
+            .item("Digest", AttributeValue::S(digest))
+            .send()
+            .await;
+        if let Err(error) = result {
+            println!("Failed to update the digest of {} in {}:\n {}", key, table, error_message(&error));
+            std::process::exit(1);
+        }
+    }
+    println!(
+        "Version {} restored {} as serial {} (new version {})",
+        version_id,
+        "successfully".green().bold(),
+        serial,
+        output.version_id().unwrap_or("null")
+    );
+}
+
+pub async fn state_history(matches: &clap::ArgMatches) {
+    match matches.subcommand() {
+        Some(("history", sub_matches)) => history(sub_matches).await,
+        Some(("diff", sub_matches)) => diff(sub_matches).await,
+        Some(("restore", sub_matches)) => restore(sub_matches).await,
+        _ => println!("No valid subcommand was used, please use the --help flag for more information"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn state(resources: Value, outputs: Value) -> TerraformState {
+        let content = json!({"version": 4, "serial": 1, "lineage": "l", "resources": resources, "outputs": outputs});
+        parse_state(&content.to_string()).unwrap()
+    }
+
+    fn bucket(attributes: Value, sensitive_attributes: Value) -> Value {
+        json!([{
+            "mode": "managed", "type": "aws_s3_bucket", "name": "logs",
+            "instances": [{"attributes": attributes, "sensitive_attributes": sensitive_attributes}]
+        }])
+    }
+
+    #[test]
+    fn flatten_nested_values() {
+        let mut out = BTreeMap::new();
+        flatten("", &json!({"a": {"b": [1, {"c": true}]}, "d": "x", "e": [], "f": {}}), &mut out);
+        let expected: BTreeMap<String, Value> = [
+            ("a.b[0]", json!(1)),
+            ("a.b[1].c", json!(true)),
+            ("d", json!("x")),
+            ("e", json!([])),
+            ("f", json!({})),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn diff_lists_added_removed_and_changed() {
+        let from = state(bucket(json!({"acl": "private", "tags": {"env": "dev"}}), json!([])), json!({}));
+        let mut to_resources = bucket(json!({"acl": "private", "tags": {"env": "prod"}}), json!([]));
+        to_resources.as_array_mut().unwrap().push(json!({
+            "mode": "managed", "type": "aws_s3_bucket", "name": "data", "instances": [{"attributes": {}}]
+        }));
+        let to = state(to_resources, json!({"name": {"value": "logs"}}));
+        assert_eq!(
+            diff_lines(&from, &to),
+            vec![
+                "+ aws_s3_bucket.data",
+                "~ aws_s3_bucket.logs",
+                "    tags.env: \"dev\" -> \"prod\"",
+                "~ output.name: (none) -> \"logs\"",
+            ]
+        );
+        assert_eq!(diff_lines(&to, &to), Vec::<String>::new());
+    }
+
+    #[test]
+    fn diff_masks_sensitive_values() {
+        let sensitive = json!([
+            [{"type": "get_attr", "value": "password"}],
+            [{"type": "get_attr", "value": "tags"}, {"type": "index", "value": {"value": "token", "type": "string"}}]
+        ]);
+        let from = state(
+            bucket(json!({"password": "old", "passwords": "a", "tags": {"token": "t1"}}), sensitive.clone()),
+            json!({"secret": {"value": "s1", "sensitive": true}}),
+        );
+        let to = state(
+            bucket(json!({"password": "new", "passwords": "b", "tags": {"token": "t2"}}), sensitive),
+            json!({"secret": {"value": "s2", "sensitive": true}}),
+        );
+        assert_eq!(
+            diff_lines(&from, &to),
+            vec![
+                "~ aws_s3_bucket.logs",
+                "    password (sensitive)",
+                "    passwords: \"a\" -> \"b\"",
+                "    tags.token (sensitive)",
+                "~ output.secret (sensitive)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn digest_tables_finds_the_lock_table() {
+        let stub = crate::commands::aws_stub::AwsStub::start(|request| match request.target() {
+            "DynamoDB_20120810.ListTables" => (200, r#"{"TableNames": ["app", "locks", "sessions"]}"#.to_string()),
+            "DynamoDB_20120810.GetItem" if request.body.contains(r#""TableName":"locks""#) => {
+                assert!(request.body.contains("states/prod/terraform.tfstate-md5"));
+                (200, r#"{"Item": {"LockID": {"S": "states/prod/terraform.tfstate-md5"}, "Digest": {"S": "abc"}}}"#.to_string())
+            }
+            "DynamoDB_20120810.GetItem" if request.body.contains(r#""TableName":"app""#) => (
+                400,
+                r#"{"__type": "com.amazonaws.dynamodb.v20120810#ValidationException", "message": "key schema mismatch"}"#
+                    .to_string(),
+            ),
+            _ => (200, "{}".to_string()),
+        })
+        .await;
+        let dynamo = aws_sdk_dynamodb::Client::new(&stub.config);
+        let tables = digest_tables(&dynamo, "states", "prod/terraform.tfstate").await.unwrap();
+        assert_eq!(tables, vec!["locks"]);
+    }
+}
@@ -1,24 +1,376 @@
-use clap::{command, Arg, Command};
+use clap::{command, Arg, ArgAction, Command};
 mod commands;
 
 fn delete_bucket_command() -> Command {
-    Command::new("delete-bucket").about("Delete a bucket")
+    Command::new("delete-bucket")
+        .about("Delete one or more buckets")
+        .arg(
+            Arg::new("bucket")
+                .long("bucket")
+                .action(ArgAction::Append)
+                .help("Name of the bucket to delete, can be repeated"),
+        )
+        .arg(
+            Arg::new("match")
+                .long("match")
+                .help("Delete every bucket whose name matches this regex"),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation"),
+        )
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .value_parser(clap::value_parser!(u32).range(1..=512))
+                .default_value("16")
+                .help("Number of DeleteObjects requests running in parallel"),
+        )
+        .arg(
+            Arg::new("parallel")
+                .long("parallel")
+                .value_parser(clap::value_parser!(u32).range(1..=64))
+                .default_value("4")
+                .help("Number of buckets emptied at the same time"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Only list what would be deleted"),
+        )
+        .arg(
+            Arg::new("allow-unverified")
+                .long("allow-unverified")
+                .action(ArgAction::SetTrue)
+                .help("Delete buckets too large to be fully checked for terraform states"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .help("Only delete keys starting with this prefix (keeps the bucket)"),
+        )
+        .arg(
+            Arg::new("older-than")
+                .long("older-than")
+                .value_parser(commands::delete_bucket::parse_age)
+                .help("Only delete objects older than this age, e.g. 30d or 12h (keeps the bucket)"),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .action(ArgAction::Append)
+                .help("Only delete keys matching this glob, can be repeated (keeps the bucket)"),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .action(ArgAction::Append)
+                .help("Never delete keys matching this glob, can be repeated (keeps the bucket)"),
+        )
+}
+
+fn locking_arg() -> Arg {
+    Arg::new("locking")
+        .long("locking")
+        .value_parser(["dynamodb", "lockfile", "both"])
+        .help("How terraform locks the states: dynamoDB table, S3 lockfile, or both while migrating (Default: dynamodb)")
 }
 
 fn init_aws_state() -> Command {
-    Command::new("init-aws-state").about("Init a dynamoDB and an S3 bucket")
+    Command::new("init-aws-state")
+        .about("Init (or audit an existing) dynamoDB table and S3 bucket")
+        .arg(Arg::new("bucket").long("bucket").help("Name of the S3 bucket to create"))
+        .arg(Arg::new("table").long("table").help("Name of the dynamoDB table to create"))
+        .arg(locking_arg())
+        .arg(
+            Arg::new("kms-key")
+                .long("kms-key")
+                .help("KMS key (ID, ARN or alias) used to encrypt the bucket (Default: AES256)"),
+        )
+        .arg(
+            Arg::new("retention")
+                .long("retention")
+                .value_parser(clap::value_parser!(i32).range(1..))
+                .default_value("90")
+                .help("Days before noncurrent state versions are deleted"),
+        )
+        .arg(
+            Arg::new("fix")
+                .long("fix")
+                .action(ArgAction::SetTrue)
+                .help("Fix the configuration drift of an existing bucket/table without asking"),
+        )
+}
+
+fn state_backend_command() -> Command {
+    Command::new("state-backend")
+        .about("Migrate or destroy a terraform state backend (S3 bucket and dynamoDB table)")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("migrate")
+                .about("Copy the terraform states to another bucket/table")
+                .arg(Arg::new("from-bucket").long("from-bucket").help("Bucket holding the states"))
+                .arg(Arg::new("from-table").long("from-table").help("Lock table of the source bucket"))
+                .arg(Arg::new("to-bucket").long("to-bucket").help("Bucket to copy the states to"))
+                .arg(
+                    Arg::new("to-table")
+                        .long("to-table")
+                        .help("Lock table of the destination bucket (Default: lock with an S3 lockfile)"),
+                )
+                .arg(
+                    Arg::new("versions")
+                        .long("versions")
+                        .action(ArgAction::SetTrue)
+                        .help("Copy every version of the states, not only the current one"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .help("Repository created by init whose backend.tf files are rewritten"),
+                )
+                .arg(
+                    Arg::new("overwrite")
+                        .long("overwrite")
+                        .action(ArgAction::SetTrue)
+                        .help("Replace the states that already exist in the destination bucket"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .action(ArgAction::SetTrue)
+                        .help("Don't ask for confirmation"),
+                ),
+        )
+        .subcommand(
+            Command::new("destroy")
+                .about("Delete an unused state bucket and its lock table")
+                .arg(Arg::new("bucket").long("bucket").help("Name of the state bucket"))
+                .arg(Arg::new("table").long("table").help("Name of the lock table"))
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .action(ArgAction::SetTrue)
+                        .help("Don't ask for confirmation"),
+                ),
+        )
+}
+
+fn locks_command() -> Command {
+    Command::new("locks")
+        .about("List the terraform locks of a dynamoDB table and force-unlock them")
+        .arg(Arg::new("table").long("table").help("Name of the lock table"))
+        .arg(
+            Arg::new("delete")
+                .long("delete")
+                .help("LockID (or lock ID) of the lock to delete"),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation"),
+        )
+}
+
+fn state_command() -> Command {
+    Command::new("state")
+        .about("Browse the resources, modules and outputs of terraform states")
+        .arg(Arg::new("bucket").long("bucket").help("Bucket holding the states"))
+        .arg(Arg::new("key").long("key").help("Key of the state to open (Default: list the states of the bucket)"))
+        .arg(
+            Arg::new("file")
+                .long("file")
+                .conflicts_with_all(["bucket", "key"])
+                .help("Local state file to open instead of a bucket"),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("history")
+                .about("List the versions of a state")
+                .arg(Arg::new("bucket").long("bucket").help("Bucket holding the states"))
+                .arg(Arg::new("key").long("key").help("Key of the state"))
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .help("Number of versions to show, newest first"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare two versions of a state resource by resource")
+                .arg(Arg::new("bucket").long("bucket").help("Bucket holding the states"))
+                .arg(Arg::new("key").long("key").help("Key of the state"))
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .required(true)
+                        .help("Version ID, latest, or a local state file"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .default_value("latest")
+                        .help("Version ID, latest, or a local state file"),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Make an older version the current state")
+                .arg(Arg::new("bucket").long("bucket").help("Bucket holding the states"))
+                .arg(Arg::new("key").long("key").help("Key of the state"))
+                .arg(
+                    Arg::new("version")
+                        .long("version")
+                        .required(true)
+                        .help("Version ID to restore (see state history)"),
+                )
+                .arg(
+                    Arg::new("table")
+                        .long("table")
+                        .help("Lock table of the backend, its digest of the state is updated (required when a table of the region keeps one)"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .action(ArgAction::SetTrue)
+                        .help("Don't ask for confirmation"),
+                ),
+        )
 }
 
 fn ecs_connect_command() -> Command {
-    Command::new("ecs").about("Connect or port forward to an ECS container")
+    Command::new("ecs")
+        .about("Connect or port forward to an ECS container")
+        .arg(Arg::new("cluster").long("cluster").help("Name of the ECS cluster"))
+        .arg(Arg::new("service").long("service").help("Name of the ECS service"))
+        .arg(Arg::new("task").long("task").help("ID of the ECS task"))
+        .arg(Arg::new("container").long("container").help("Name of the container"))
+        .arg(
+            Arg::new("command")
+                .long("command")
+                .default_value("/bin/sh")
+                .help("Command to execute in the container"),
+        )
 }
 
 fn ec2_connect_command() -> Command {
-    Command::new("ec2").about("Connect or port forward to an EC2 container")
+    Command::new("ec2")
+        .about("Connect or port forward to an EC2 container")
+        .arg(Arg::new("instance").long("instance").help("ID or Name tag of the instance"))
+}
+
+// Shared by the commands starting port forwards
+fn keep_alive_arg() -> Arg {
+    Arg::new("keep-alive")
+        .long("keep-alive")
+        .action(ArgAction::SetTrue)
+        .help("Reconnect dropped port forwards, finding the target again (new task of the service, new instance with the tag)")
 }
 
 fn port_forward() -> Command {
-    Command::new("port-forward").about("Forward a port from a container/EC2 to your local machine")
+    Command::new("port-forward")
+        .about("Forward a port from a container/EC2 to your local machine")
+        .arg(
+            Arg::new("type")
+                .long("type")
+                .value_parser(["ec2", "ecs"])
+                .help("Type of resource to port forward from"),
+        )
+        .arg(Arg::new("instance").long("instance").help("ID or Name tag of the instance"))
+        .arg(Arg::new("cluster").long("cluster").help("Name of the ECS cluster"))
+        .arg(Arg::new("service").long("service").help("Name of the ECS service"))
+        .arg(Arg::new("task").long("task").help("ID of the ECS task"))
+        .arg(Arg::new("container").long("container").help("Name of the container"))
+        .arg(Arg::new("host").long("host").help("Remote host to forward"))
+        .arg(
+            Arg::new("endpoint")
+                .long("endpoint")
+                .conflicts_with("host")
+                .help("RDS instance/cluster or ElastiCache replication group (ID[:ROLE], e.g. my-cluster:reader) to forward, sets the host and remote port"),
+        )
+        .arg(
+            Arg::new("remote-port")
+                .long("remote-port")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("Remote port to forward"),
+        )
+        .arg(
+            Arg::new("local-port")
+                .long("local-port")
+                .value_parser(commands::port_forward::parse_local_port)
+                .help("Local port to listen on, or auto for any free port"),
+        )
+        .arg(keep_alive_arg())
+}
+
+// Forwards to run, shared by `forward` and `forward start`
+fn forward_args() -> Vec<Arg> {
+    vec![
+        Arg::new("name")
+            .value_name("PROFILE")
+            .action(ArgAction::Append)
+            .help("Profiles of the config file to forward"),
+        Arg::new("forward")
+            .long("forward")
+            .action(ArgAction::Append)
+            .value_name("TARGET:HOST:REMOTE_PORT:LOCAL_PORT")
+            .help("Port forward to run, the target is an instance ID or Name tag, tag:<key>=<value>, ecs-service:<cluster>/<service>[/<container>] or ecs:<cluster>_<task>_<runtime_id>"),
+        Arg::new("file")
+            .long("file")
+            .help("TOML file of [[forward]] tables with target, host, remote_port and local_port"),
+        keep_alive_arg(),
+    ]
+}
+
+fn forward_command() -> Command {
+    Command::new("forward")
+        .about("Run several port forwards at once and watch them")
+        .args(forward_args())
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("start")
+                .about("Start port forwards, in the background with --detach")
+                .args(forward_args())
+                .arg(
+                    Arg::new("detach")
+                        .long("detach")
+                        .short('d')
+                        .action(ArgAction::SetTrue)
+                        .help("Hand the forwards to a background daemon, they survive closing the terminal"),
+                ),
+        )
+        .subcommand(Command::new("list").about("List the port forwards running in the background"))
+        .subcommand(
+            Command::new("stop").about("Stop a port forward running in the background").arg(
+                Arg::new("id")
+                    .required(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .help("ID of the forward (see forward list)"),
+            ),
+        )
+        .subcommand(Command::new("daemon").hide(true))
+}
+
+fn connect_command() -> Command {
+    Command::new("connect")
+        .about("Open the shell or port forward of a profile of the config file")
+        .arg(
+            Arg::new("name")
+                .value_name("PROFILE")
+                .required(true)
+                .help("Profile of the config file"),
+        )
+        .arg(keep_alive_arg())
 }
 
 fn module_command() -> Command {
@@ -29,7 +381,48 @@ fn module_command() -> Command {
 }
 
 fn init_command() -> Command {
-    Command::new("init").about("Init a terraform repository")
+    Command::new("init")
+        .about("Init a terraform repository")
+        .arg(Arg::new("project").long("project").help("Name of the project"))
+        .arg(
+            Arg::new("environments")
+                .long("environments")
+                .help("Comma separated list of environments"),
+        )
+        .arg(
+            Arg::new("accounts")
+                .long("accounts")
+                .help("Comma separated list of accounts"),
+        )
+        .arg(
+            Arg::new("path")
+                .long("path")
+                .help("Where to create the repository (Default: current directory)"),
+        )
+        .arg(
+            Arg::new("state-bucket")
+                .long("state-bucket")
+                .help("S3 bucket holding the terraform states"),
+        )
+        .arg(
+            Arg::new("lock-table")
+                .long("lock-table")
+                .help("DynamoDB table used to lock the terraform states"),
+        )
+        .arg(locking_arg())
+        .arg(
+            Arg::new("create-backend")
+                .long("create-backend")
+                .action(ArgAction::SetTrue)
+                .help("Create the state bucket and lock table (or check them if they exist)"),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation"),
+        )
 }
 
 #[::tokio::main]
@@ -38,23 +431,52 @@ async fn main() {
         .propagate_version(true)
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .global(true)
+                .help("AWS profile to use (Default: AWS_PROFILE or the default profile)"),
+        )
+        .arg(
+            Arg::new("region")
+                .long("region")
+                .global(true)
+                .help("AWS region to use (Default: AWS_REGION or the profile region)"),
+        )
+        .arg(
+            Arg::new("aws-cli")
+                .long("aws-cli")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Run sessions through the aws CLI and session-manager-plugin instead of the built-in client"),
+        )
         .subcommand(init_command())
         .subcommand(module_command())
         .subcommand(ecs_connect_command())
         .subcommand(ec2_connect_command())
         .subcommand(init_aws_state())
         .subcommand(port_forward())
+        .subcommand(forward_command())
+        .subcommand(connect_command())
         .subcommand(delete_bucket_command())
+        .subcommand(state_backend_command())
+        .subcommand(locks_command())
+        .subcommand(state_command())
         .get_matches();
 
     match matches.subcommand() {
-        Some(("init", _sub_matches)) => commands::init::init(),
+        Some(("init", sub_matches)) => commands::init::init(sub_matches).await,
         Some(("module", sub_matches)) => commands::module::module(sub_matches),
-        Some(("ecs", _sub_matches)) => commands::ecs_connect::ecs_connect().await,
-        Some(("ec2", _sub_matches)) => commands::ec2_connect::ec2_connect().await,
-        Some(("init-aws-state", _sub_matches)) => commands::inti_aws_state::init_aws_state().await,
-        Some(("port-forward", _sub_matches)) => commands::port_forward::port_forward().await,
-        Some(("delete-bucket", _sub_matches)) => commands::delete_bucket::delete_bucket().await,
+        Some(("ecs", sub_matches)) => commands::ecs_connect::ecs_connect(sub_matches).await,
+        Some(("ec2", sub_matches)) => commands::ec2_connect::ec2_connect(sub_matches).await,
+        Some(("init-aws-state", sub_matches)) => commands::inti_aws_state::init_aws_state(sub_matches).await,
+        Some(("port-forward", sub_matches)) => commands::port_forward::port_forward(sub_matches).await,
+        Some(("delete-bucket", sub_matches)) => commands::delete_bucket::delete_bucket(sub_matches).await,
+        Some(("state-backend", sub_matches)) => commands::state_backend::state_backend(sub_matches).await,
+        Some(("locks", sub_matches)) => commands::locks::locks(sub_matches).await,
+        Some(("state", sub_matches)) => commands::state::state(sub_matches).await,
+        Some(("forward", sub_matches)) => commands::forward::forward(sub_matches).await,
+        Some(("connect", sub_matches)) => commands::profile::connect(sub_matches).await,
         _ => println!("No valid subcommand was used, please use the --help flag for more information"),
     }
 }